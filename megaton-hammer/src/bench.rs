@@ -0,0 +1,63 @@
+//! The `bench` command for timing clean builds
+//!
+//! The build records how long each phase took, so the runs can be broken down
+//! by phase. Phases that are skipped (like the check without a `check` section)
+//! are only listed if they ran.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The phases recorded by the current build
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// Record that `phase` of the build took `duration`
+pub fn record_phase(phase: &'static str, duration: Duration) {
+    PHASES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((phase, duration));
+}
+
+/// Take the phases recorded since the last call
+pub fn take_phases() -> Vec<(&'static str, Duration)> {
+    std::mem::take(&mut *PHASES.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// The times of one build
+pub struct Run {
+    pub total: Duration,
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+/// Print the min, median and max time of the runs, in total and for each phase
+pub fn print_summary(runs: &[Run]) {
+    let mut rows = vec![(
+        "total",
+        runs.iter().map(|run| run.total).collect::<Vec<_>>(),
+    )];
+    for run in runs {
+        for (phase, duration) in &run.phases {
+            match rows.iter_mut().find(|(name, _)| name == phase) {
+                Some((_, durations)) => durations.push(*duration),
+                None => rows.push((phase, vec![*duration])),
+            }
+        }
+    }
+    println!(
+        "{:<10} {:>10} {:>10} {:>10}",
+        "phase", "min", "median", "max"
+    );
+    for (phase, mut durations) in rows {
+        durations.sort();
+        let min = durations[0];
+        let median = durations[durations.len() / 2];
+        let max = durations[durations.len() - 1];
+        println!(
+            "{:<10} {:>9.2}s {:>9.2}s {:>9.2}s",
+            phase,
+            min.as_secs_f64(),
+            median.as_secs_f64(),
+            max.as_secs_f64()
+        );
+    }
+}
@@ -1,76 +1,158 @@
 use std::collections::BTreeSet;
-use std::io::{BufRead, BufReader};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::Instant;
 
 use crate::config::Check;
+use crate::diagnostic::Diagnostic;
 use crate::error::Error;
-use crate::{errorln, hintln, infoln};
+use crate::objdump::{dump_exports, dump_symbol_table, dump_symbols, parse_syms_file};
+use crate::{errorln, hintln, infoln, process, sarif, verboseln};
 
-pub fn check_symbols<SRoot, SBinary, SObjDump>(
-    root: SRoot,
+/// Load the symbols in the symbol files and reference binaries of `check`
+///
+/// The symbols don't depend on the module, so they can be loaded while it's being built.
+/// They are loaded with at most `io_jobs` threads, or one thread each if `None`.
+pub fn load_symbols(
+    root: &Path,
+    objdump: &Path,
+    check: &Check,
+    io_jobs: Option<u16>,
+) -> Result<BTreeSet<String>, Error> {
+    let sources = check
+        .symbols
+        .iter()
+        .map(|path| (path, false))
+        .chain(check.reference_binaries.iter().map(|path| (path, true)))
+        .collect::<Vec<_>>();
+    let load = |(path, is_binary): (&String, bool)| {
+        if is_binary {
+            return timed(&format!("objdump -T {path}"), || {
+                dump_symbols(objdump, root.join(path))
+            });
+        }
+        timed(&format!("loading {path}"), || {
+            let file_content = std::fs::read_to_string(root.join(path))
+                .map_err(|e| Error::AccessFile(path.to_string(), e))?;
+            let mut symbols = BTreeSet::new();
+            parse_syms_file(path, &file_content, &mut symbols)?;
+            Ok(symbols)
+        })
+    };
+    let jobs = io_jobs
+        .map_or(sources.len(), usize::from)
+        .min(sources.len());
+    let next = AtomicUsize::new(0);
+    // the symbol sources are independent, so they are loaded in parallel. Each thread
+    // takes the next source until all of them are loaded
+    std::thread::scope(|scope| {
+        let handles = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut symbols = BTreeSet::new();
+                    while let Some(source) = sources.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        symbols.extend(load(*source)?);
+                    }
+                    Ok::<_, Error>(symbols)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut loaded_symbols = BTreeSet::new();
+        for handle in handles {
+            loaded_symbols.extend(join_thread(handle)?);
+        }
+        Ok(loaded_symbols)
+    })
+}
+
+/// Known symbols that are loaded on another thread while the module is built
+///
+/// The thread is joined when this is dropped, so it doesn't outlive the build if
+/// the symbols are never used, like when the build fails.
+pub struct PreloadedSymbols(Option<JoinHandle<Result<BTreeSet<String>, Error>>>);
+
+impl PreloadedSymbols {
+    /// Start loading the symbols with `load` on another thread
+    pub fn spawn<F>(load: F) -> Self
+    where
+        F: FnOnce() -> Result<BTreeSet<String>, Error> + Send + 'static,
+    {
+        Self(Some(std::thread::spawn(load)))
+    }
+
+    /// Wait for the symbols to be loaded
+    pub fn join(mut self) -> Result<BTreeSet<String>, Error> {
+        match self.0.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e)),
+            None => Ok(BTreeSet::new()),
+        }
+    }
+}
+
+impl Drop for PreloadedSymbols {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            // the symbols are not needed, only the thread is waited for
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Check that all dynamic symbols in `binary` can be resolved
+///
+/// `load` gets the known symbols, like with [`load_symbols`]. It's called while the symbols
+/// of `binary` are dumped, so it can also wait for symbols that are already being loaded.
+/// If `sarif` is specified, a SARIF report of the findings is written to that path,
+/// along with the compiler diagnostics.
+pub fn check_symbols<SBinary, SObjDump, F>(
     binary: SBinary,
     objdump: SObjDump,
     check: &Check,
+    load: F,
+    sarif: Option<(&Path, &[Diagnostic])>,
 ) -> Result<(), Error>
 where
-    SRoot: AsRef<Path>,
     SBinary: AsRef<Path>,
     SObjDump: AsRef<Path>,
+    F: FnOnce() -> Result<BTreeSet<String>, Error>,
 {
     let binary = binary.as_ref();
     infoln!("Checking", "{}", binary.display());
 
-    let binary_path = binary.display().to_string();
-    let args = vec!["-T", &binary_path];
-    let command = format!("{} {}", objdump.as_ref().display(), args.join(" "));
+    let objdump = objdump.as_ref();
 
-    let mut child = Command::new(objdump.as_ref())
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| Error::Subprocess(command.clone(), "cannot spawn child".to_string(), e))?;
-
-    let mut elf_symbols = BTreeSet::new();
-    if let Some(stdout) = child.stdout.take() {
-        let stdout = BufReader::new(stdout).lines().flatten();
-        parse_objdump_syms("(elf objdump output)", stdout, &mut elf_symbols)?;
-    }
-
-    if let Some(stderr) = child.stderr.take() {
-        let stderr = BufReader::new(stderr);
-        for line in stderr.lines() {
-            if let Ok(line) = line {
-                errorln!("Error", "{}", line);
-            }
-        }
-    }
-
-    let status = child
-        .wait()
-        .map_err(|e| Error::Subprocess(command.clone(), "cannot wait for child".to_string(), e))?;
-    if !status.success() {
+    let (elf_symbols, loaded_symbols) = std::thread::scope(|scope| {
+        let elf_handle = scope.spawn(|| {
+            timed(&format!("objdump -T {}", binary.display()), || {
+                dump_symbol_table(objdump, binary)
+            })
+        });
+        let loaded_symbols = load();
+        let elf_symbols = join_thread(elf_handle)?;
+        Ok::<_, Error>((elf_symbols, loaded_symbols?))
+    })?;
+    let Some(elf_symbols) = elf_symbols else {
+        // without the table, nothing would be missing and the check would pass
+        errorln!(
+            "Error",
+            "Cannot check the symbols, since `{}` has no dynamic symbol table",
+            binary.display()
+        );
         return Err(Error::CheckError);
-    }
-
-    std::fs::remove_file(binary).map_err(|e| Error::AccessFile(binary.display().to_string(), e))?;
-
-    for symbol in &check.ignore {
-        elf_symbols.remove(symbol);
-    }
+    };
 
-    let mut loaded_symbols = BTreeSet::new();
-    for path in &check.symbols {
-        let file_content = std::fs::read_to_string(root.as_ref().join(path))
-            .map_err(|e| Error::AccessFile(path.to_string(), e))?;
-        parse_objdump_syms(&path, file_content.lines(), &mut loaded_symbols)?;
+    let missing_symbols = timed("filtering", || {
+        compute_missing(elf_symbols, &loaded_symbols, &check.ignore)
+    });
+    if let Some((sarif, diagnostics)) = sarif {
+        sarif::write_sarif(sarif, binary, &missing_symbols, diagnostics)?;
+        infoln!("Saved", "`{}`", sarif.display());
     }
-
-    let missing_symbols = elf_symbols
-        .into_iter()
-        .filter(|symbol| !loaded_symbols.contains(symbol))
-        .collect::<Vec<_>>();
     if !missing_symbols.is_empty() {
         errorln!("Error", "There are unresolved symbols:");
         errorln!("Error", "");
@@ -98,48 +180,218 @@ where
     Ok(())
 }
 
-fn parse_objdump_syms<Iter, Str>(
-    id: &str,
-    raw_symbols: Iter,
-    output: &mut BTreeSet<String>,
+/// Run the built-in checks with `builtin`, and the `validators` on `binary` at the same time
+///
+/// Both are run to completion, so all failures are printed.
+pub fn check_with_validators<F>(
+    root: &Path,
+    binary: &Path,
+    validators: &[String],
+    builtin: F,
 ) -> Result<(), Error>
 where
-    Iter: IntoIterator<Item = Str>,
-    Str: AsRef<str>,
+    F: FnOnce() -> Result<(), Error>,
 {
-    infoln!("Parsing", "{}", id);
-    let mut iter = raw_symbols.into_iter();
-    let old_size = output.len();
-    while let Some(line) = iter.next() {
-        if line.as_ref() == "DYNAMIC SYMBOL TABLE:" {
-            break;
-        }
+    if validators.is_empty() {
+        return builtin();
     }
+    std::thread::scope(|scope| {
+        let handle = scope.spawn(|| run_validators(root, binary, validators));
+        let result = builtin();
+        let validators_result = join_thread(handle);
+        result.and(validators_result)
+    })
+}
 
-    // Example
-    // # 0000000000000000      DF *UND*	0000000000000000 nnsocketGetPeerName
-    //                   ^ spaces      ^ this is a tag
+/// Run the external `validators` in parallel, with the path to `binary` as the last argument
+///
+/// The stderr of the validators that failed is printed.
+fn run_validators(root: &Path, binary: &Path, validators: &[String]) -> Result<(), Error> {
+    let binary = binary
+        .canonicalize()
+        .map_err(|e| Error::AccessFile(binary.display().to_string(), e))?;
+    let outputs = std::thread::scope(|scope| {
+        let handles = validators
+            .iter()
+            .map(|validator| {
+                let binary = &binary;
+                scope.spawn(move || {
+                    let mut args = validator.split_whitespace();
+                    let program = args.next().ok_or_else(|| {
+                        Error::ParseConfig("empty command in `external-validators`".to_string())
+                    })?;
+                    verboseln!(1, "Running", "{} {}", validator, binary.display());
+                    process::output(
+                        Command::new(program)
+                            .args(args)
+                            .arg(binary)
+                            .current_dir(root),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().map(join_thread).collect::<Vec<_>>()
+    });
 
-    while let Some(line) = iter.next() {
-        let line = line.as_ref();
-        if line.len() <= 25 {
+    let mut failed = 0;
+    for (validator, output) in validators.iter().zip(outputs) {
+        let output = output?;
+        if output.status.success() {
+            infoln!("Checked", "`{}` passed", validator);
             continue;
         }
-        let symbol = match line[25..].splitn(2, ' ').skip(1).next() {
-            Some(symbol) => symbol,
-            None => {
-                return Err(Error::InvalidObjdump(
-                    id.to_string(),
-                    format!("invalid line: {}", line),
-                ))
+        failed += 1;
+        errorln!("Error", "`{}` failed: {}", validator, output.status);
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            errorln!("Error", "  {}", line);
+        }
+    }
+    if failed > 0 {
+        errorln!("Error", "{} external validator(s) failed!", failed);
+        return Err(Error::CheckError);
+    }
+    Ok(())
+}
+
+/// Check that `binary` exports exactly the global symbols in the version script at `verfile`
+///
+/// The unexpected exports (and missing ones) are written to `report`, one per line.
+pub fn check_exports(
+    binary: &Path,
+    objdump: &Path,
+    verfile: &Path,
+    report: &Path,
+) -> Result<(), Error> {
+    infoln!("Checking", "exports of {}", binary.display());
+    let content = std::fs::read_to_string(verfile)
+        .map_err(|e| Error::AccessFile(verfile.display().to_string(), e))?;
+    let expected = parse_verfile_globals(&content);
+    let exports = dump_exports(objdump, binary)?;
+
+    let unexpected = exports.difference(&expected).collect::<Vec<_>>();
+    let missing = expected.difference(&exports).collect::<Vec<_>>();
+    if unexpected.is_empty() && missing.is_empty() {
+        if report.exists() {
+            std::fs::remove_file(report)
+                .map_err(|e| Error::AccessFile(report.display().to_string(), e))?;
+        }
+        infoln!("Checked", "Exports match the version script");
+        return Ok(());
+    }
+
+    let mut content = String::new();
+    for symbol in &unexpected {
+        errorln!("Error", "Unexpected export: {}", symbol);
+        content.push_str(&format!("unexpected {symbol}\n"));
+    }
+    for symbol in &missing {
+        errorln!("Error", "Missing export: {}", symbol);
+        content.push_str(&format!("missing {symbol}\n"));
+    }
+    crate::fs::write_file(report, content)?;
+    infoln!("Saved", "`{}`", report.display());
+    hintln!(
+        "Hint",
+        "Symbols are exported when they have default visibility. Check for `-fvisibility` overrides and visibility attributes."
+    );
+    Err(Error::CheckError)
+}
+
+/// Get the symbols in the `global:` section of a version script
+fn parse_verfile_globals(content: &str) -> BTreeSet<String> {
+    let mut globals = BTreeSet::new();
+    let mut in_global = false;
+    for token in content.split_whitespace() {
+        match token {
+            "global:" => in_global = true,
+            "local:" => in_global = false,
+            _ if in_global => {
+                let symbol = token.trim_end_matches(';');
+                if !symbol.is_empty() {
+                    globals.insert(symbol.to_string());
+                }
             }
-        };
-        output.insert(symbol.to_string());
+            _ => {}
+        }
     }
+    globals
+}
+
+/// Get the symbols in `elf_symbols` that are not in `loaded_symbols` or `ignore`, in order
+pub fn compute_missing(
+    elf_symbols: BTreeSet<String>,
+    loaded_symbols: &BTreeSet<String>,
+    ignore: &[String],
+) -> Vec<String> {
+    let ignore = ignore.iter().collect::<BTreeSet<_>>();
+    elf_symbols
+        .into_iter()
+        .filter(|symbol| !loaded_symbols.contains(symbol) && !ignore.contains(symbol))
+        .collect()
+}
+
+/// Run a phase of the check, printing how long it took in verbose mode
+fn timed<T>(phase: &str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    verboseln!(
+        1,
+        "Timing",
+        "{} took {:.2}s",
+        phase,
+        start.elapsed().as_secs_f64()
+    );
+    result
+}
+
+/// Join a scoped thread, propagating the panic if the thread panicked
+fn join_thread<T>(handle: std::thread::ScopedJoinHandle<'_, T>) -> T {
+    handle
+        .join()
+        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+}
 
-    if output.len() == old_size {
-        hintln!("Warning", "No symbols found in `{}`", id);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(symbols: &[&str]) -> BTreeSet<String> {
+        symbols.iter().map(|s| s.to_string()).collect()
     }
 
-    Ok(())
+    #[test]
+    fn compute_missing_skips_loaded_and_ignored() {
+        let elf = set(&["a", "b", "c", "d"]);
+        let loaded = set(&["b"]);
+        let ignore = vec!["d".to_string(), "not_in_elf".to_string()];
+        assert_eq!(compute_missing(elf, &loaded, &ignore), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn compute_missing_empty_sets() {
+        assert!(compute_missing(BTreeSet::new(), &set(&["a"]), &["b".to_string()]).is_empty());
+        assert_eq!(
+            compute_missing(set(&["b", "a"]), &BTreeSet::new(), &[]),
+            vec!["a", "b"]
+        );
+        assert!(compute_missing(set(&["a"]), &set(&["a"]), &[]).is_empty());
+    }
+
+    #[test]
+    fn compute_missing_large_sets() {
+        let elf = (0..100_000)
+            .map(|i| format!("sym_{:06}", i))
+            .collect::<BTreeSet<_>>();
+        let loaded = elf
+            .iter()
+            .filter(|symbol| !symbol.ends_with('7'))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let ignore = vec!["sym_000007".to_string(), "sym_099997".to_string()];
+        let missing = compute_missing(elf, &loaded, &ignore);
+        assert_eq!(missing.len(), 10_000 - 2);
+        assert_eq!(missing.first().map(String::as_str), Some("sym_000017"));
+        assert_eq!(missing.last().map(String::as_str), Some("sym_099987"));
+        assert!(missing.windows(2).all(|pair| pair[0] < pair[1]));
+    }
 }
@@ -1,13 +1,14 @@
 //! Config structures
 
-use std::{collections::BTreeMap, path::Path};
+use std::{collections::BTreeMap, path::Path, sync::OnceLock};
 
+use schemars::JsonSchema;
 use serde::{de::Visitor, Deserialize, Serialize};
 
 use crate::error::Error;
 
 /// Config data read from Megaton.toml
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MegatonConfig {
     /// The `[module]` section
     pub module: Module,
@@ -20,41 +21,299 @@ pub struct MegatonConfig {
 
     /// The `[check]` section (for checking unresolved dynamic symbols)
     pub check: Option<ProfileContainer<Check>>,
+
+    /// The `[exefs]` section (for packaging the outputs as an ExeFS)
+    pub exefs: Option<Exefs>,
+
+    /// The `[romfs]` section (for packing a RomFS)
+    pub romfs: Option<Romfs>,
+
+    /// The `[deploy]` section (for the `deploy` command)
+    pub deploy: Option<Deploy>,
 }
 
 impl MegatonConfig {
     /// Load a config from a file
+    ///
+    /// If `path` is `-`, the config is read from stdin.
     pub fn from_path<S>(path: S) -> Result<Self, Error>
+    where
+        S: AsRef<Path>,
+    {
+        Self::from_path_with_overrides(path, &[])
+    }
+
+    /// Load a config from a file, then replace values according to `overrides`
+    ///
+    /// Each override is `<key>=<value>`, where the key is a dotted path like `make.entry`
+    /// or `make.profiles.foo.defines`, and the value is a TOML value (like `true`, `0x10` or
+    /// `["A", "B"]`), or a string if it's not valid TOML. The overrides are applied in order
+    /// to the file, before any profile is applied. Keys that are not in the config are errors.
+    pub fn from_path_with_overrides<S>(path: S, overrides: &[String]) -> Result<Self, Error>
     where
         S: AsRef<Path>,
     {
         let path = path.as_ref();
-        let config = std::fs::read_to_string(path)
-            .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
-        let config = toml::from_str(&config).map_err(|e| Error::ParseConfig(e.to_string()))?;
+        let config = if path == Path::new("-") {
+            // stdin can only be read once, but the config can be loaded multiple times
+            static STDIN: OnceLock<String> = OnceLock::new();
+            match STDIN.get() {
+                Some(config) => config.clone(),
+                None => {
+                    let config = std::io::read_to_string(std::io::stdin())
+                        .map_err(|e| Error::AccessFile("<stdin>".to_string(), e))?;
+                    STDIN.get_or_init(|| config).clone()
+                }
+            }
+        } else {
+            std::fs::read_to_string(path)
+                .map_err(|e| Error::AccessFile(path.display().to_string(), e))?
+        };
+        if overrides.is_empty() {
+            let config = toml::from_str(&config).map_err(|e| Error::ParseConfig(e.to_string()))?;
+            return Ok(config);
+        }
+        let mut table = toml::from_str::<toml::Table>(&config)
+            .map_err(|e| Error::ParseConfig(e.to_string()))?;
+        let mut keys = Vec::new();
+        for config_override in overrides {
+            keys.push(apply_override(&mut table, config_override)?);
+        }
+        let config = Self::deserialize(table)
+            .map_err(|e| Error::ParseConfig(format!("after `--config-override`: {e}")))?;
+        // unknown keys are ignored when deserializing, so they are missing when serialized again
+        let serialized =
+            toml::Table::try_from(&config).map_err(|e| Error::ParseConfig(e.to_string()))?;
+        for key in keys {
+            let mut value = serialized.get(&key[0]);
+            for part in &key[1..] {
+                value = value.and_then(|value| value.get(part));
+            }
+            if value.is_none() {
+                return Err(Error::ParseConfig(format!(
+                    "`{}` in `--config-override` is not a config key",
+                    key.join(".")
+                )));
+            }
+        }
         Ok(config)
     }
+
+    /// Get the config with the profile `name` applied to the sections that have profiles
+    pub fn resolve_profile(&self, name: &str) -> ResolvedConfig {
+        ResolvedConfig {
+            module: self.module.clone(),
+            lang: self.lang.clone(),
+            make: self.make.get_profile(name),
+            check: self.check.as_ref().map(|check| check.get_profile(name)),
+            exefs: self.exefs.clone(),
+            romfs: self.romfs.clone(),
+            deploy: self.deploy.clone(),
+        }
+    }
+}
+
+/// Apply a `<key>=<value>` override to `table`, and return the parts of the key
+fn apply_override(table: &mut toml::Table, config_override: &str) -> Result<Vec<String>, Error> {
+    let invalid = |reason: &str| {
+        Error::ParseConfig(format!(
+            "invalid `--config-override` `{}`: {}",
+            config_override, reason
+        ))
+    };
+    let (key, raw_value) = config_override
+        .split_once('=')
+        .ok_or_else(|| invalid("expected `<key>=<value>`"))?;
+    let key = key
+        .trim()
+        .split('.')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if key.iter().any(|part| part.is_empty()) {
+        return Err(invalid("the key has an empty part"));
+    }
+    let raw_value = raw_value.trim();
+    let value = toml::from_str::<toml::Table>(&format!("value = {raw_value}"))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw_value.to_string()));
+
+    let (last, parents) = key
+        .split_last()
+        .ok_or_else(|| invalid("the key is empty"))?;
+    let mut current = table;
+    for part in parents {
+        current = current
+            .entry(part.clone())
+            .or_insert_with(|| toml::Value::Table(Default::default()))
+            .as_table_mut()
+            .ok_or_else(|| invalid(&format!("`{part}` is not a table")))?;
+    }
+    current.insert(last.clone(), value);
+    Ok(key)
+}
+
+/// Config with the sections that have profiles resolved to one profile
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolvedConfig {
+    pub module: Module,
+    pub lang: Option<Lang>,
+    pub make: Make,
+    pub check: Option<Check>,
+    pub exefs: Option<Exefs>,
+    pub romfs: Option<Romfs>,
+    pub deploy: Option<Deploy>,
 }
 
 /// Config in the `[module]` section
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Module {
     /// The name of the module, used as the target name of the final binary.
     pub name: String,
     /// The title ID as a 64-bit integer, used for generating the npdm file.
+    ///
+    /// Required unless `title_id_from` is set.
+    #[serde(default)]
     pub title_id: u64,
+    /// Read the title ID from a JSON file instead, as `<file>#<key>`
+    ///
+    /// The file is relative to Megaton.toml, and the key is a dotted path to the value,
+    /// like `npdm.json#title_id` or `meta.json#app.ids.0`. The value can be an integer,
+    /// or a hex string with or without the `0x` prefix.
+    pub title_id_from: Option<String>,
+    /// The expected SHA-256 of the npdm template, as a hex string
+    ///
+    /// The npdm is not generated if the template has a different hash, which
+    /// is shown in the error.
+    pub npdm_template_sha256: Option<String>,
+    /// Set to true to suppress the warning when `title_id` doesn't look like an application ID
+    pub allow_any_title_id: Option<bool>,
+    /// The file name (without extension) of the output ELF, NSO and map files.
+    ///
+    /// Defaults to `name`. The NX module name embedded in the binary is still `name`.
+    pub output_name: Option<String>,
+    /// The profile to use when `--profile` and `MEGATON_PROFILE` are not set
+    pub default_profile: Option<String>,
 }
 
 impl Module {
+    /// Get the file name (without extension) of the outputs
+    pub fn output_name(&self) -> &str {
+        self.output_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Read the title ID from the file in `title_id_from`, relative to `root_dir`
+    ///
+    /// Fails if neither or both of `title_id` and `title_id_from` are set. After this,
+    /// only `title_id` is set.
+    pub fn resolve_title_id(&mut self, root_dir: &Path) -> Result<(), Error> {
+        let Some(title_id_from) = &self.title_id_from else {
+            if self.title_id == 0 {
+                return Err(Error::ParseConfig(
+                    "`title-id` is missing in the `module` section".to_string(),
+                ));
+            }
+            return Ok(());
+        };
+        if self.title_id != 0 {
+            return Err(Error::ParseConfig(
+                "`title-id` and `title-id-from` cannot both be set".to_string(),
+            ));
+        }
+        let invalid = |reason: &str| {
+            Error::ParseConfig(format!(
+                "invalid `title-id-from` `{title_id_from}`: {reason}"
+            ))
+        };
+        let (file, key) = title_id_from
+            .split_once('#')
+            .ok_or_else(|| invalid("expected `<file>#<key>`"))?;
+        let path = root_dir.join(file);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        let json = serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(|e| invalid(&format!("cannot parse `{file}`: {e}")))?;
+        let mut value = &json;
+        for part in key.split('.') {
+            let next = match value {
+                serde_json::Value::Array(array) => {
+                    part.parse::<usize>().ok().and_then(|i| array.get(i))
+                }
+                _ => value.get(part),
+            };
+            value = next.ok_or_else(|| invalid(&format!("`{key}` is not in `{file}`")))?;
+        }
+        let title_id = match value {
+            serde_json::Value::Number(number) => number.as_u64(),
+            serde_json::Value::String(string) => {
+                let hex = string
+                    .strip_prefix("0x")
+                    .or_else(|| string.strip_prefix("0X"))
+                    .unwrap_or(string);
+                u64::from_str_radix(hex, 16).ok()
+            }
+            _ => None,
+        };
+        match title_id {
+            Some(title_id) if title_id != 0 => {
+                // the resolved config has only the title ID, so it can be loaded again
+                self.title_id = title_id;
+                self.title_id_from = None;
+                Ok(())
+            }
+            _ => Err(invalid(&format!("`{value}` is not a valid title ID"))),
+        }
+    }
+
     /// Get the title ID as a lower-case hex string
     pub fn title_id_hex(&self) -> String {
         format!("{:016x}", self.title_id)
     }
+
+    /// Check if the title ID looks like a valid application program ID
+    ///
+    /// Returns a message describing the problem if it doesn't. This is only a heuristic
+    /// to catch copy-paste errors, so the caller should only warn.
+    pub fn check_title_id(&self) -> Option<String> {
+        if self.allow_any_title_id.unwrap_or_default() {
+            return None;
+        }
+        let id = self.title_id;
+        if id >> 56 != 0x01 {
+            return Some(format!(
+                "Title ID `0x{:016x}` is not in the program ID range (0x0100000000000000 - 0x01ffffffffffffff).",
+                id
+            ));
+        }
+        if id < 0x0100000000010000 {
+            return Some(format!(
+                "Title ID `0x{:016x}` is a system program ID, not an application ID.",
+                id
+            ));
+        }
+        match id & 0x1fff {
+            0 => None,
+            0x800 => Some(format!(
+                "Title ID `0x{:016x}` looks like an update ID. Did you mean the application ID `0x{:016x}`?",
+                id,
+                id & !0x1fff
+            )),
+            x if x & 0x1000 != 0 => Some(format!(
+                "Title ID `0x{:016x}` looks like an add-on content (DLC) ID. Did you mean the application ID `0x{:016x}`?",
+                id,
+                id & !0x1fff
+            )),
+            _ => Some(format!(
+                "Title ID `0x{:016x}` does not look like an application ID, which usually ends with `000` or `2000`.",
+                id
+            )),
+        }
+    }
 }
 
 /// Config in the `[lang]` section
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Lang {
     /// Options for the clangd language server
@@ -62,7 +321,7 @@ pub struct Lang {
 }
 
 /// Language options for clangd
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct LangClangd {
     /// The path to output the `.clangd` file
     pub output: String,
@@ -77,10 +336,12 @@ impl Default for LangClangd {
 }
 
 /// Config in the `[make]` section
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Make {
     /// Entry point symbol for the module
+    ///
+    /// The `#[megaton::bootstrap]` macro in the runtime exports `megaton_rust_main`.
     pub entry: Option<String>,
 
     /// If built-in compiler flags should not be added
@@ -88,27 +349,169 @@ pub struct Make {
     /// `-I` and `-D` from includes and defines will still be added
     pub no_default_flags: Option<bool>,
 
+    /// If paths in the compiler commands should be relative to the build directory
+    ///
+    /// By default, sources and includes are passed to the compiler as absolute paths.
+    /// Setting this to true makes the commands independent of where the project is
+    /// checked out, so compiler caches like `ccache`/`sccache` can share results.
+    pub relative_paths: Option<bool>,
+
+    /// If ignored files should be skipped when finding sources
+    ///
+    /// When enabled, sources ignored by `.gitignore` or `.megatonignore` files
+    /// (and hidden files, like editor temp files) are not compiled. The ignore
+    /// files are respected even if the project is not in a git repository.
+    pub respect_gitignore: Option<bool>,
+
+    /// If objects should be rebuilt based on the content of the inputs instead of modified times
+    ///
+    /// When enabled, the source and headers of each object are hashed after a build.
+    /// In the next build, objects whose inputs have the same content are not compiled
+    /// again, even if the files were touched (for example, by switching git branches).
+    /// Hashing has a cost for every object on every build, so this only pays off when
+    /// files are often touched without changing.
+    pub hash_based: Option<bool>,
+
+    /// The C++ standard, passed to the compiler as `-std=<standard>`
+    ///
+    /// Defaults to `gnu++20`, which is a default flag and is not added if `no-default-flags`
+    /// is set. A standard specified here is always added.
+    pub cpp_standard: Option<String>,
+
+    /// The C standard, passed to the compiler as `-std=<standard>`
+    ///
+    /// Defaults to the compiler's default. A standard specified here is always added,
+    /// even if `no-default-flags` is set.
+    pub c_standard: Option<String>,
+
     /// C/C++ Source directories, relative to Megaton.toml
     #[serde(default)]
     pub sources: Vec<String>,
 
-    /// C/C++ Include directories, relative to Megaton.toml
+    /// C/C++ Include directories, relative to Megaton.toml or absolute
+    ///
+    /// Directories that don't exist (yet) are still passed to the compiler, with a warning.
     #[serde(default)]
     pub includes: Vec<String>,
 
+    /// Include directories for third-party headers, relative to Megaton.toml or absolute
+    ///
+    /// These are passed with `-isystem`, so warnings in their headers don't fail the build
+    /// with `-Werror`. `#include` searches the `includes` directories first, then
+    /// the libnx and portlibs includes (which are also `-isystem`), then these directories,
+    /// then the compiler's system directories.
+    #[serde(default)]
+    pub system_includes: Vec<String>,
+
+    /// Include directories for assembly sources, relative to Megaton.toml or absolute
+    ///
+    /// These are passed with `-I` only when assembling, so `.include` and `#include` in
+    /// `.s` files find files in them. The `includes` are not passed to the assembler.
+    #[serde(default)]
+    pub as_includes: Vec<String>,
+
     /// Extra defines
     ///
     /// These will be added to the command line as `-D<define>`
     #[serde(default)]
     pub defines: Vec<String>,
 
+    /// Environment variables to define as string literals
+    ///
+    /// For example, `GIT_HASH` is added as `-DGIT_HASH="<value>"`, so it can be used
+    /// like `const char* hash = GIT_HASH;`. The value is quoted for the shell, so it
+    /// can contain any characters. Changing a value rebuilds everything.
+    #[serde(default)]
+    pub defines_from_env: Vec<String>,
+
+    /// If an unset variable in `defines-from-env` should only be a warning
+    ///
+    /// By default, it fails the build. When allowed, the define is skipped.
+    pub allow_missing_env: Option<bool>,
+
     /// Linker scripts
     #[serde(default)]
     pub ld_scripts: Vec<String>,
 
+    /// Linker script templates, relative to Megaton.toml
+    ///
+    /// The scripts are generated in the target directory before linking, with
+    /// `${MODULE_NAME}`, `${OUTPUT_NAME}`, `${ENTRY}`, `${TITLE_ID}` (16 hex digits)
+    /// and `${TARGET_DIR}` substituted, and are passed to the linker after `ld-scripts`.
+    /// Changes to the generated scripts cause a relink.
+    #[serde(default)]
+    pub ld_script_templates: Vec<String>,
+
+    /// Libraries to link
+    ///
+    /// Each entry is one of:
+    /// - a name, like `foo`, linked with `-lfoo` (finds `libfoo.a` in the library paths)
+    /// - a file name starting with `:`, like `:libfoo_custom.a`, linked with `-l:libfoo_custom.a`
+    /// - a path containing `/`, relative to Megaton.toml or absolute, linked directly
+    #[serde(default)]
+    pub libraries: Vec<String>,
+
+    /// Extra source file extensions, and how to compile them
+    ///
+    /// For example, `{ ext = "cc", kind = "cpp" }` compiles `.cc` files like `.cpp` files.
+    /// The built-in `.c`, `.cpp` and `.s` extensions can't be remapped.
+    #[serde(default)]
+    pub extra_source_types: Vec<ExtraSourceType>,
+
+    /// Precompiled objects (`.o` or `.a`) to link, relative to Megaton.toml or absolute
+    ///
+    /// They are linked before the libraries, and the module is relinked when they change.
+    /// Each object must exist.
+    #[serde(default)]
+    pub extra_objects: Vec<String>,
+
+    /// Keep the debug info out of the ELF with `-gsplit-dwarf`
+    ///
+    /// The debug sections of the ELF are copied to `<output>.debug` in the target
+    /// directory, and the `.dwo` files with the rest of the debug info are packaged
+    /// into `<output>.dwp` next to it.
+    pub split_debug: Option<bool>,
+
+    /// The compiler to use. Defaults to `gcc` from devkitA64
+    ///
+    /// `clang` uses `clang` and `clang++` from `PATH` with `--target=aarch64-none-elf`, and
+    /// leaves out the default flags that only gcc supports (`-mtp=soft` and `-fpermissive`).
+    /// Linking still uses the compiler, so the linker it finds must support the devkitA64 flags.
+    pub compiler: Option<Compiler>,
+
     /// Extra macros
     #[serde(default)]
     pub extra: Vec<KeyVal>,
+
+    /// Files containing extra flags, relative to Megaton.toml
+    ///
+    /// Each entry maps a make variable (like `CFLAGS` or `LDFLAGS`) to a file.
+    /// The file has one flag per line. Blank lines and lines starting with `#` are ignored.
+    /// The flags are appended to the variable the same way as `extra`.
+    #[serde(default)]
+    pub flag_files: Vec<KeyVal>,
+
+    /// Raw flags for the C/C++ compiler
+    ///
+    /// These are appended verbatim at the very end of `CFLAGS` and `CXXFLAGS`,
+    /// after the default flags and the flags from `extra`
+    #[serde(default)]
+    pub raw_cc: Vec<String>,
+
+    /// Raw flags for the linker
+    ///
+    /// These are appended verbatim at the very end of `LDFLAGS`,
+    /// after the default flags and the flags from `extra`
+    #[serde(default)]
+    pub raw_ld: Vec<String>,
+
+    /// Globs of sources (relative to Megaton.toml) where compiler warnings fail the build
+    ///
+    /// This is for when `-Werror` is removed for third-party code, but your own code
+    /// should still be free of warnings. The default flags have `-Werror`, which already
+    /// fails the build on any warning.
+    #[serde(default)]
+    pub deny_warnings_in: Vec<String>,
 }
 
 impl Profilable for Make {
@@ -119,43 +522,222 @@ impl Profilable for Make {
         if let Some(no_default_flags) = other.no_default_flags {
             self.no_default_flags = Some(no_default_flags);
         }
+        if let Some(relative_paths) = other.relative_paths {
+            self.relative_paths = Some(relative_paths);
+        }
+        if let Some(respect_gitignore) = other.respect_gitignore {
+            self.respect_gitignore = Some(respect_gitignore);
+        }
+        if let Some(hash_based) = other.hash_based {
+            self.hash_based = Some(hash_based);
+        }
+        if let Some(split_debug) = other.split_debug {
+            self.split_debug = Some(split_debug);
+        }
+        if let Some(compiler) = other.compiler {
+            self.compiler = Some(compiler);
+        }
+        if let Some(cpp_standard) = other.cpp_standard.clone() {
+            self.cpp_standard = Some(cpp_standard);
+        }
+        if let Some(c_standard) = other.c_standard.clone() {
+            self.c_standard = Some(c_standard);
+        }
         self.sources.extend(other.sources.iter().cloned());
         self.includes.extend(other.includes.iter().cloned());
+        self.system_includes
+            .extend(other.system_includes.iter().cloned());
+        self.as_includes.extend(other.as_includes.iter().cloned());
         self.defines.extend(other.defines.iter().cloned());
+        self.defines_from_env
+            .extend(other.defines_from_env.iter().cloned());
+        if let Some(allow_missing_env) = other.allow_missing_env {
+            self.allow_missing_env = Some(allow_missing_env);
+        }
         self.ld_scripts.extend(other.ld_scripts.iter().cloned());
+        self.ld_script_templates
+            .extend(other.ld_script_templates.iter().cloned());
+        self.libraries.extend(other.libraries.iter().cloned());
+        self.extra_source_types
+            .extend(other.extra_source_types.iter().cloned());
+        self.extra_objects
+            .extend(other.extra_objects.iter().cloned());
         self.extra.extend(other.extra.iter().cloned());
+        self.flag_files.extend(other.flag_files.iter().cloned());
+        self.raw_cc.extend(other.raw_cc.iter().cloned());
+        self.raw_ld.extend(other.raw_ld.iter().cloned());
+        self.deny_warnings_in
+            .extend(other.deny_warnings_in.iter().cloned());
     }
 }
 
 /// The `check` section
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Check {
     /// Symbols to ignore
     #[serde(default)]
     pub ignore: Vec<String>,
     /// Paths to *.syms file (output of objdump) that contains dynamic symbols accessible by the module
+    ///
+    /// The files can also be a plain list of symbols, one per line, with `#` comments
     #[serde(default)]
     pub symbols: Vec<String>,
+    /// Paths to ELF files whose dynamic symbols are accessible by the module
+    ///
+    /// `objdump -T` is run on these at check time, so they don't need to be dumped
+    /// into `*.syms` files first. All sources are loaded in parallel.
+    #[serde(default)]
+    pub reference_binaries: Vec<String>,
+    /// If the ELF must export exactly the symbols in the `global:` section of the verfile
+    ///
+    /// This catches symbols that are exported by accident, like when `-fvisibility=hidden`
+    /// is overridden. The differences are written to `exports.txt` in the report directory.
+    pub strict_exports: Option<bool>,
+    /// The directory for the report files of the check, relative to Megaton.toml
+    ///
+    /// Defaults to the target directory of the profile. The directory is created if needed.
+    /// `megaton clean` only removes it if it's inside `target/megaton`.
+    pub report_dir: Option<String>,
+    /// If the ELF is renamed to `<name>.bad.elf` when the check fails, instead of removed
+    ///
+    /// Either way, the ELF is linked and checked again in the next build. Defaults to true.
+    pub rename_on_fail: Option<bool>,
+    /// Commands to validate the ELF with, after it's linked
+    ///
+    /// Each command is split on whitespace (without quoting) and run in the directory of
+    /// Megaton.toml, with the absolute path of the ELF appended as the last argument.
+    /// The validators run in parallel with the built-in checks, and a non-zero exit
+    /// status fails the check.
+    #[serde(default)]
+    pub external_validators: Vec<String>,
 }
 
 impl Profilable for Check {
     fn extend(&mut self, other: &Self) {
         self.ignore.extend(other.ignore.iter().cloned());
         self.symbols.extend(other.symbols.iter().cloned());
+        self.reference_binaries
+            .extend(other.reference_binaries.iter().cloned());
+        if let Some(strict_exports) = other.strict_exports {
+            self.strict_exports = Some(strict_exports);
+        }
+        if let Some(report_dir) = other.report_dir.clone() {
+            self.report_dir = Some(report_dir);
+        }
+        if let Some(rename_on_fail) = other.rename_on_fail {
+            self.rename_on_fail = Some(rename_on_fail);
+        }
+        self.external_validators
+            .extend(other.external_validators.iter().cloned());
     }
 }
 
+/// Config in the `[exefs]` section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Exefs {
+    /// The file name of the module NSO in the ExeFS, such as `main` or `subsdk9`.
+    ///
+    /// Defaults to the output name of the module
+    pub nso: Option<String>,
+
+    /// Extra files to put in the ExeFS, such as other prebuilt `subsdkN` modules.
+    ///
+    /// Each entry maps the file name in the ExeFS to a path relative to Megaton.toml
+    #[serde(default)]
+    pub files: Vec<KeyVal>,
+}
+
+/// Config in the `[romfs]` section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Romfs {
+    /// The RomFS directory to pack, relative to Megaton.toml
+    pub dir: String,
+}
+
+/// Config in the `[deploy]` section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Deploy {
+    /// How to transfer the outputs
+    pub method: DeployMethod,
+
+    /// The destination directory
+    ///
+    /// For `copy`, this is a local path, like the ExeFS directory on a mounted SD card.
+    /// For `ftp`, this is the path on the server, like `/atmosphere/contents/<title-id>/exefs`.
+    pub path: String,
+
+    /// The FTP server, like the IP address of the console. Required for `ftp`
+    pub host: Option<String>,
+
+    /// The FTP port. Defaults to 5000, the default of `sys-ftpd`
+    pub port: Option<u16>,
+
+    /// The FTP user. Defaults to `anonymous`
+    pub user: Option<String>,
+
+    /// The FTP password. Defaults to empty
+    pub password: Option<String>,
+}
+
+/// The compiler in `make.compiler`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compiler {
+    /// gcc from devkitA64
+    #[default]
+    Gcc,
+    /// clang from `PATH`
+    Clang,
+}
+
+/// An extra source file extension in `make.extra-source-types`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExtraSourceType {
+    /// The file extension, without the `.`
+    pub ext: String,
+    /// How to compile the files
+    pub kind: SourceKind,
+}
+
+/// The compiler invocations for sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceKind {
+    /// Compiled as C, like `.c` files
+    C,
+    /// Compiled as C++, like `.cpp` files
+    Cpp,
+    /// Assembled with the preprocessor, like `.s` files
+    As,
+}
+
+/// How to deploy the outputs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeployMethod {
+    /// Copy to a local directory
+    #[default]
+    Copy,
+    /// Upload to an FTP server
+    Ftp,
+}
+
 /// Generic config section that can be extended with profiles
 ///
 /// For example, the `[make]` section can have profiles with `[make.profiles.<name>]`
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ProfileContainer<T>
 where
     T: Profilable + Clone,
 {
     #[serde(flatten)]
     pub base: T,
+    /// Profiles that extend the base section, selected with `--profile`
     #[serde(default)]
     pub profiles: BTreeMap<String, T>,
 }
@@ -212,6 +794,44 @@ impl Serialize for KeyVal {
     }
 }
 
+impl JsonSchema for KeyVal {
+    fn schema_name() -> String {
+        "KeyVal".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{InstanceType, Metadata, ObjectValidation, SchemaObject};
+        let value = SchemaObject {
+            instance_type: Some(
+                vec![
+                    InstanceType::String,
+                    InstanceType::Number,
+                    InstanceType::Boolean,
+                ]
+                .into(),
+            ),
+            ..Default::default()
+        };
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "A mapping with a single key and a non-mapping value".to_string(),
+                ),
+                ..Default::default()
+            })),
+            instance_type: Some(InstanceType::Object.into()),
+            object: Some(Box::new(ObjectValidation {
+                min_properties: Some(1),
+                max_properties: Some(1),
+                additional_properties: Some(Box::new(value.into())),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 struct KeyValVisitor;
 impl<'de> Visitor<'de> for KeyValVisitor {
     type Value = KeyVal;
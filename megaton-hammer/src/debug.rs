@@ -0,0 +1,112 @@
+//! Separate debug info with `make.split-debug`
+//!
+//! The objects are compiled with `-gsplit-dwarf`, so most of the debug info is in
+//! `.dwo` files next to the objects in the build directory, and the ELF only references
+//! them. The debug sections of the ELF are copied to `<output>.debug` in the target
+//! directory with objcopy, and the `.dwo` files are packaged into `<output>.dwp` next to
+//! it with dwp. The build directory is removed when the Makefile changes, so the debug info
+//! must not depend on it. The NSO never has debug info, since only the segments are
+//! converted.
+//!
+//! To debug, load `<output>.debug` as the symbol file at the module's base address
+//! (for example, `add-symbol-file <output>.debug -o <base>` in GDB). GDB looks for the
+//! package at the path of the symbol file with `.dwp` appended, so link it there once
+//! (`ln -s <output>.dwp <output>.debug.dwp`). Both files are replaced by each build, so
+//! the link keeps working.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::error::Error;
+use crate::fs::{FileSystem, RealFileSystem};
+use crate::process;
+use crate::{infoln, verboseln};
+
+/// Copy the debug sections of `elf_path` to `debug_path`
+///
+/// This is skipped if `debug_path` is newer than the ELF.
+pub fn extract_debug_info(objcopy: &Path, elf_path: &Path, debug_path: &Path) -> Result<(), Error> {
+    if is_debug_info_up_to_date(&RealFileSystem, elf_path, debug_path) {
+        return Ok(());
+    }
+    let args = vec![
+        "--only-keep-debug".to_string(),
+        elf_path.display().to_string(),
+        debug_path.display().to_string(),
+    ];
+    let command = format!("{} {}", objcopy.display(), args.join(" "));
+    verboseln!(1, "Running", "{}", command);
+    let mut child = process::spawn(Command::new(objcopy).args(&args).stdout(Stdio::null()))?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::ObjcopyError(status));
+    }
+    if let Some(name) = debug_path.file_name() {
+        infoln!("Created", "{}", name.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Package the `.dwo` files referenced by `elf_path` into `dwp_path`
+///
+/// This is skipped if `dwp_path` is newer than the ELF. A `.dwo` file only changes
+/// when its object is compiled again, which relinks the ELF.
+pub fn package_split_dwarf(dwp: &Path, elf_path: &Path, dwp_path: &Path) -> Result<(), Error> {
+    if is_debug_info_up_to_date(&RealFileSystem, elf_path, dwp_path) {
+        return Ok(());
+    }
+    // dwp opens the `.dwo` files relative to the current directory, not the compile
+    // directory in the debug info. The objects are compiled next to the ELF
+    let build_dir = elf_path.parent().unwrap_or(Path::new("."));
+    let args = vec![
+        "-e".to_string(),
+        absolute(elf_path)?,
+        "-o".to_string(),
+        absolute(dwp_path)?,
+    ];
+    let command = format!("{} {}", dwp.display(), args.join(" "));
+    verboseln!(1, "Running", "{}", command);
+    let mut child = process::spawn(
+        Command::new(dwp)
+            .args(&args)
+            .current_dir(build_dir)
+            .stdout(Stdio::null()),
+    )?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::DwpError(status));
+    }
+    if let Some(name) = dwp_path.file_name() {
+        infoln!("Created", "{}", name.to_string_lossy());
+    }
+    Ok(())
+}
+
+fn absolute(path: &Path) -> Result<String, Error> {
+    std::path::absolute(path)
+        .map(|path| path.display().to_string())
+        .map_err(|e| Error::AccessFile(path.display().to_string(), e))
+}
+
+/// Check if `debug_path` is newer than `elf_path`
+fn is_debug_info_up_to_date(fs: &impl FileSystem, elf_path: &Path, debug_path: &Path) -> bool {
+    fs.is_up_to_date(debug_path, fs.get_modified_time(elf_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFileSystem;
+
+    #[test]
+    fn debug_info_is_extracted_again_when_elf_is_newer() {
+        let elf = Path::new("build/example.elf");
+        let debug = Path::new("target/example.debug");
+        let fs = MemoryFileSystem::default().file(elf, "", 10);
+        assert!(!is_debug_info_up_to_date(&fs, elf, debug));
+        let fs = fs.file(debug, "", 10);
+        assert!(is_debug_info_up_to_date(&fs, elf, debug));
+        let fs = fs.file(elf, "", 11);
+        assert!(!is_debug_info_up_to_date(&fs, elf, debug));
+    }
+}
@@ -0,0 +1,269 @@
+//! Deploying the outputs to a console or SD card
+//!
+//! The ExeFS files (or the NSO and npdm, if there is no ExeFS) are either copied to a
+//! local directory, like a mounted SD card, or uploaded to an FTP server,
+//! like the one from `sys-ftpd` running on the console.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{Deploy, DeployMethod};
+use crate::error::Error;
+use crate::{infoln, verboseln};
+
+/// Default port of `sys-ftpd`
+const DEFAULT_FTP_PORT: u16 = 5000;
+const FTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Deploy `files` (file name at the destination, local path) according to `deploy`
+///
+/// Returns the number of bytes transferred.
+pub fn deploy_files(files: &[(String, PathBuf)], deploy: &Deploy) -> Result<u64, Error> {
+    let bytes = match deploy.method {
+        DeployMethod::Copy => copy_files(files, Path::new(&deploy.path))?,
+        DeployMethod::Ftp => upload_files(files, deploy)?,
+    };
+    Ok(bytes)
+}
+
+/// Copy the files to the local directory `dest_dir`
+fn copy_files(files: &[(String, PathBuf)], dest_dir: &Path) -> Result<u64, Error> {
+    std::fs::create_dir_all(dest_dir)
+        .map_err(|e| Error::AccessDirectory(dest_dir.display().to_string(), e))?;
+    let mut bytes = 0;
+    for (name, path) in files {
+        let dest = dest_dir.join(name);
+        // the source is opened first, so a missing output is reported as itself
+        let mut source =
+            File::open(path).map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        let mut dest_file =
+            File::create(&dest).map_err(|e| Error::AccessFile(dest.display().to_string(), e))?;
+        bytes += std::io::copy(&mut source, &mut dest_file)
+            .map_err(|e| Error::AccessFile(dest.display().to_string(), e))?;
+        infoln!("Copied", "`{}`", dest.display());
+    }
+    Ok(bytes)
+}
+
+/// Upload the files to the FTP server in `deploy`
+fn upload_files(files: &[(String, PathBuf)], deploy: &Deploy) -> Result<u64, Error> {
+    let host = deploy
+        .host
+        .as_deref()
+        .ok_or_else(|| Error::DeployError("`host` is required for FTP".to_string()))?;
+    let port = deploy.port.unwrap_or(DEFAULT_FTP_PORT);
+    let mut ftp = FtpClient::connect(host, port)?;
+    let user = deploy.user.as_deref().unwrap_or("anonymous");
+    let password = deploy.password.as_deref().unwrap_or("");
+    ftp.login(user, password)?;
+    ftp.command("TYPE I", &[200])?;
+    ftp.change_dir(&deploy.path)?;
+
+    let mut bytes = 0;
+    for (name, path) in files {
+        let data =
+            std::fs::read(path).map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        ftp.store(name, &data)?;
+        bytes += data.len() as u64;
+        infoln!(
+            "Uploaded",
+            "`{}/{}`",
+            deploy.path.trim_end_matches('/'),
+            name
+        );
+    }
+    // the files are already uploaded, so a failed QUIT is not an error
+    let _ = ftp.command("QUIT", &[221]);
+    Ok(bytes)
+}
+
+/// Minimal FTP client for uploading files in passive mode
+struct FtpClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl FtpClient {
+    fn connect(host: &str, port: u16) -> Result<Self, Error> {
+        let address = format!("{host}:{port}");
+        verboseln!(1, "Connecting", "{}", address);
+        let stream = address
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| Error::DeployError(format!("cannot resolve `{address}`")))
+            .and_then(|socket| {
+                TcpStream::connect_timeout(&socket, FTP_TIMEOUT)
+                    .map_err(|e| Error::DeployError(format!("cannot connect to `{address}`: {e}")))
+            })?;
+        let _ = stream.set_read_timeout(Some(FTP_TIMEOUT));
+        let writer = stream
+            .try_clone()
+            .map_err(|e| Error::DeployError(e.to_string()))?;
+        let mut client = Self {
+            reader: BufReader::new(stream),
+            writer,
+        };
+        client.expect(&[220])?;
+        Ok(client)
+    }
+
+    fn login(&mut self, user: &str, password: &str) -> Result<(), Error> {
+        let (code, _) = self.command(&format!("USER {user}"), &[230, 331])?;
+        if code == 331 {
+            self.command(&format!("PASS {password}"), &[230])?;
+        }
+        Ok(())
+    }
+
+    /// Change to `path`, creating the directories that don't exist
+    fn change_dir(&mut self, path: &str) -> Result<(), Error> {
+        if path.starts_with('/') {
+            self.command("CWD /", &[250])?;
+        }
+        for part in path.split('/').filter(|part| !part.is_empty()) {
+            if self.command(&format!("CWD {part}"), &[250]).is_err() {
+                self.command(&format!("MKD {part}"), &[257])?;
+                self.command(&format!("CWD {part}"), &[250])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let (_, message) = self.command("PASV", &[227])?;
+        let port = parse_pasv_port(&message)
+            .ok_or_else(|| Error::DeployError(format!("invalid PASV response: {message}")))?;
+        // the address in the response is often the server's internal address,
+        // so the data connection goes to the same host as the control connection
+        let mut address = self
+            .writer
+            .peer_addr()
+            .map_err(|e| Error::DeployError(e.to_string()))?;
+        address.set_port(port);
+        let mut data_stream = TcpStream::connect_timeout(&address, FTP_TIMEOUT)
+            .map_err(|e| Error::DeployError(format!("cannot open data connection: {e}")))?;
+        self.command(&format!("STOR {name}"), &[125, 150])?;
+        data_stream
+            .write_all(data)
+            .map_err(|e| Error::DeployError(format!("cannot upload `{name}`: {e}")))?;
+        drop(data_stream);
+        self.expect(&[226, 250])?;
+        Ok(())
+    }
+
+    /// Send a command and expect one of the reply codes
+    fn command(&mut self, command: &str, codes: &[u32]) -> Result<(u32, String), Error> {
+        // don't print the password
+        let printed = if command.starts_with("PASS ") {
+            "PASS ***"
+        } else {
+            command
+        };
+        verboseln!(2, "FTP", "{}", printed);
+        self.writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .map_err(|e| Error::DeployError(format!("cannot send `{printed}`: {e}")))?;
+        self.expect(codes)
+    }
+
+    /// Read a (possibly multi-line) reply and check the code
+    fn expect(&mut self, codes: &[u32]) -> Result<(u32, String), Error> {
+        let mut line = String::new();
+        let (code, message) = loop {
+            line.clear();
+            let read = self
+                .reader
+                .read_line(&mut line)
+                .map_err(|e| Error::DeployError(format!("cannot read reply: {e}")))?;
+            if read == 0 {
+                return Err(Error::DeployError("connection closed".to_string()));
+            }
+            let line = line.trim_end();
+            // the last line of a reply is `<code> <message>`
+            if line.len() >= 4 && line.as_bytes()[3] == b' ' {
+                if let Ok(code) = line[..3].parse::<u32>() {
+                    break (code, line[4..].to_string());
+                }
+            }
+        };
+        verboseln!(2, "FTP", "{} {}", code, message);
+        if !codes.contains(&code) {
+            return Err(Error::DeployError(format!("{code} {message}")));
+        }
+        Ok((code, message))
+    }
+}
+
+/// Get the port from a `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` message
+fn parse_pasv_port(message: &str) -> Option<u16> {
+    let start = message.find('(')?;
+    let end = message[start..].find(')')? + start;
+    let numbers = message[start + 1..end]
+        .split(',')
+        .map(|n| n.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match numbers.as_slice() {
+        [_, _, _, _, high, low] => Some(u16::from_be_bytes([*high, *low])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pasv_port_valid() {
+        assert_eq!(
+            parse_pasv_port("Entering Passive Mode (192,168,0,2,19,137)"),
+            Some(5001)
+        );
+        assert_eq!(parse_pasv_port("(0,0,0,0, 255 , 255)."), Some(65535));
+    }
+
+    #[test]
+    fn parse_pasv_port_invalid() {
+        // the numbers are bytes, so a larger number doesn't overflow the port
+        assert_eq!(parse_pasv_port("(192,168,0,2,256,0)"), None);
+        assert_eq!(parse_pasv_port("(192,168,0,2,65535,65535)"), None);
+        assert_eq!(parse_pasv_port("(192,168,0,2,-1,0)"), None);
+        assert_eq!(parse_pasv_port("(192,168,0,2,19)"), None);
+        assert_eq!(parse_pasv_port("Entering Passive Mode"), None);
+        assert_eq!(parse_pasv_port("(192,168,0,2,19,137"), None);
+    }
+
+    #[test]
+    fn copy_files_reports_missing_source() {
+        let dir = crate::fs::test_dir("deploy-copy");
+        let source = dir.join("module.nso");
+        crate::fs::write_file(&source, "NSO0").unwrap();
+        let missing = dir.join("main.npdm");
+        let files = vec![
+            ("module.nso".to_string(), source),
+            ("main.npdm".to_string(), missing.clone()),
+        ];
+        let dest = dir.join("sd");
+        match copy_files(&files, &dest) {
+            Err(Error::AccessFile(path, _)) => assert_eq!(path, missing.display().to_string()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(dest.join("module.nso").exists());
+        assert!(!dest.join("main.npdm").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copy_files_counts_bytes() {
+        let dir = crate::fs::test_dir("deploy-copy-bytes");
+        let source = dir.join("module.nso");
+        crate::fs::write_file(&source, "NSO0").unwrap();
+        let files = vec![("subsdk9".to_string(), source)];
+        assert_eq!(copy_files(&files, &dir.join("sd")).unwrap(), 4);
+        assert_eq!(std::fs::read(dir.join("sd/subsdk9")).unwrap(), b"NSO0");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,136 @@
+//! Compiler diagnostics, parsed from GCC's JSON output or from the text output.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+/// The flag to make GCC print the diagnostics as JSON
+///
+/// It's passed with the `DIAGNOSTICS_FLAGS` make variable, so it's not part of the
+/// generated makefile.
+pub const JSON_FORMAT_FLAG: &str = "-fdiagnostics-format=json";
+
+/// A diagnostic (warning, error, etc.) from the compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The kind, like `warning`, `error` or `fatal error`
+    pub kind: String,
+    pub message: String,
+    /// The file of the diagnostic, if it has a location
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl Diagnostic {
+    pub fn is_warning(&self) -> bool {
+        self.kind == "warning"
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.kind.ends_with("error")
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+            if let Some(line) = self.line {
+                write!(f, "{}:", line)?;
+                if let Some(column) = self.column {
+                    write!(f, "{}:", column)?;
+                }
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+/// A diagnostic object in GCC's JSON output
+#[derive(Deserialize)]
+struct JsonDiagnostic {
+    kind: String,
+    message: String,
+    #[serde(default)]
+    locations: Vec<JsonLocation>,
+}
+
+#[derive(Deserialize)]
+struct JsonLocation {
+    caret: JsonPosition,
+}
+
+#[derive(Deserialize)]
+struct JsonPosition {
+    file: String,
+    line: u32,
+    column: u32,
+}
+
+/// Parse a line of GCC's JSON output, which is an array of diagnostics
+///
+/// Returns `None` if the line is not JSON, so it can be parsed as text instead.
+pub fn parse_json(line: &str) -> Option<Vec<Diagnostic>> {
+    if !line.starts_with('[') {
+        return None;
+    }
+    let diagnostics = serde_json::from_str::<Vec<JsonDiagnostic>>(line).ok()?;
+    let diagnostics = diagnostics
+        .into_iter()
+        .map(|diagnostic| {
+            let caret = diagnostic
+                .locations
+                .into_iter()
+                .next()
+                .map(|location| location.caret);
+            Diagnostic {
+                kind: diagnostic.kind,
+                message: diagnostic.message,
+                line: caret.as_ref().map(|caret| caret.line),
+                column: caret.as_ref().map(|caret| caret.column),
+                file: caret.map(|caret| caret.file),
+            }
+        })
+        .collect();
+    Some(diagnostics)
+}
+
+/// Parse a `<file>:<line>:<column>: <kind>: <message>` line from the compiler
+pub fn parse_text(line: &str) -> Option<Diagnostic> {
+    // the diagnostics are colored, so the escape sequences are removed first
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip `ESC [`, the parameters, and the final byte
+            chars.next();
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+            continue;
+        }
+        plain.push(c);
+    }
+    let (kind, location, message) =
+        ["warning", "error", "fatal error"]
+            .into_iter()
+            .find_map(|kind| {
+                let (location, message) = plain.split_once(&format!(": {}: ", kind))?;
+                Some((kind, location, message))
+            })?;
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file = parts.next()?;
+    Some(Diagnostic {
+        kind: kind.to_string(),
+        message: message.to_string(),
+        file: Some(file.to_string()),
+        line: Some(line),
+        column: Some(column),
+    })
+}
@@ -0,0 +1,241 @@
+//! Minimal ELF reader for extracting the GNU build-id
+//!
+//! Only 64-bit little-endian ELFs are supported, which is what the Switch toolchain produces.
+
+use std::path::Path;
+
+use crate::error::Error;
+
+const SHT_NOTE: u32 = 7;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// A section from the section header table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub section_type: u32,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Read the GNU build-id of the ELF at `path` as a hex string
+///
+/// Returns `None` if the ELF has no build-id note.
+pub fn read_build_id<S>(path: S) -> Result<Option<String>, Error>
+where
+    S: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let data = read_elf(path)?;
+    for section in read_sections(path, &data)? {
+        if section.section_type != SHT_NOTE {
+            continue;
+        }
+        let notes = section_data(&data, &section).ok_or_else(|| {
+            Error::InvalidElf(
+                path.display().to_string(),
+                "note section is out of bounds".to_string(),
+            )
+        })?;
+        let build_id = find_build_id(notes)
+            .map_err(|detail| Error::InvalidElf(path.display().to_string(), detail.to_string()))?;
+        if let Some(build_id) = build_id {
+            let hex = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+            return Ok(Some(hex));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the sections of the ELF at `path`
+pub fn read_section_headers<S>(path: S) -> Result<Vec<Section>, Error>
+where
+    S: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let data = read_elf(path)?;
+    read_sections(path, &data)
+}
+
+/// Read the ELF at `path` and check that it's supported
+fn read_elf(path: &Path) -> Result<Vec<u8>, Error> {
+    let data = std::fs::read(path).map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    let invalid = |detail: &str| Error::InvalidElf(path.display().to_string(), detail.to_string());
+
+    if data.len() < 0x40 || &data[0..4] != b"\x7fELF" {
+        return Err(invalid("not an ELF file"));
+    }
+    if data[4] != 2 || data[5] != 1 {
+        return Err(invalid("only 64-bit little-endian ELFs are supported"));
+    }
+    Ok(data)
+}
+
+/// Parse the section header table, with the names resolved from the section name table
+fn read_sections(path: &Path, data: &[u8]) -> Result<Vec<Section>, Error> {
+    let invalid = |detail: &str| Error::InvalidElf(path.display().to_string(), detail.to_string());
+
+    let section_offset = read_u64(data, 0x28).ok_or_else(|| invalid("truncated header"))?;
+    let section_size = read_u16(data, 0x3A).ok_or_else(|| invalid("truncated header"))?;
+    let section_count = read_u16(data, 0x3C).ok_or_else(|| invalid("truncated header"))?;
+    let name_index = read_u16(data, 0x3E).ok_or_else(|| invalid("truncated header"))?;
+
+    let section_offset = usize::try_from(section_offset)
+        .map_err(|_| invalid("section header table is out of bounds"))?;
+    let mut sections = Vec::new();
+    let mut name_offsets = Vec::new();
+    for i in 0..section_count as usize {
+        let header = i
+            .checked_mul(section_size as usize)
+            .and_then(|offset| offset.checked_add(section_offset))
+            .ok_or_else(|| invalid("section header table is out of bounds"))?;
+        let read_u32_at = |offset: usize| read_u32(data, header.checked_add(offset)?);
+        let read_u64_at = |offset: usize| read_u64(data, header.checked_add(offset)?);
+        let truncated = || invalid("truncated section header");
+        let name_offset = read_u32_at(0).ok_or_else(truncated)?;
+        let section_type = read_u32_at(0x4).ok_or_else(truncated)?;
+        name_offsets.push(name_offset as usize);
+        sections.push(Section {
+            name: String::new(),
+            section_type,
+            offset: read_u64_at(0x18).ok_or_else(truncated)?,
+            size: read_u64_at(0x20).ok_or_else(truncated)?,
+        });
+    }
+
+    // names are optional for the purposes here, so they are left empty if the table is broken
+    let names = sections
+        .get(name_index as usize)
+        .and_then(|section| section_data(data, section))
+        .unwrap_or_default();
+    for (section, name_offset) in sections.iter_mut().zip(name_offsets) {
+        if let Some(name) = names.get(name_offset..) {
+            let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+            section.name = String::from_utf8_lossy(&name[..end]).to_string();
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Get the data of a section in the file. `None` if it's out of bounds
+fn section_data<'a>(data: &'a [u8], section: &Section) -> Option<&'a [u8]> {
+    let start = usize::try_from(section.offset).ok()?;
+    let end = start.checked_add(usize::try_from(section.size).ok()?)?;
+    data.get(start..end)
+}
+
+/// Find the descriptor of the `GNU` build-id note in a note section
+///
+/// Returns an error if a note is out of bounds.
+fn find_build_id(mut notes: &[u8]) -> Result<Option<&[u8]>, &'static str> {
+    const OUT_OF_BOUNDS: &str = "note is out of bounds";
+    // each note is: namesz, descsz, type, name (4-byte aligned), desc (4-byte aligned)
+    while notes.len() >= 12 {
+        let name_size = read_u32(notes, 0).ok_or(OUT_OF_BOUNDS)? as usize;
+        let desc_size = read_u32(notes, 4).ok_or(OUT_OF_BOUNDS)? as usize;
+        let note_type = read_u32(notes, 8).ok_or(OUT_OF_BOUNDS)?;
+        let name_end = 12usize.checked_add(name_size).ok_or(OUT_OF_BOUNDS)?;
+        let desc_start = name_size
+            .checked_next_multiple_of(4)
+            .and_then(|size| size.checked_add(12))
+            .ok_or(OUT_OF_BOUNDS)?;
+        let desc_end = desc_start.checked_add(desc_size).ok_or(OUT_OF_BOUNDS)?;
+        let next = desc_size
+            .checked_next_multiple_of(4)
+            .and_then(|size| size.checked_add(desc_start))
+            .ok_or(OUT_OF_BOUNDS)?;
+        let name = notes.get(12..name_end).ok_or(OUT_OF_BOUNDS)?;
+        if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return notes
+                .get(desc_start..desc_end)
+                .ok_or(OUT_OF_BOUNDS)
+                .map(Some);
+        }
+        // the padding of the last note can be missing
+        notes = notes.get(next..).unwrap_or_default();
+    }
+    Ok(None)
+}
+
+/// Read `N` bytes at `offset`. `None` if it's out of bounds
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    read_bytes(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    read_bytes(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    read_bytes(data, offset).map(u64::from_le_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ELF header with the section header table at `shoff`
+    fn header(shoff: u64, shentsize: u16, shnum: u16) -> Vec<u8> {
+        let mut data = vec![0u8; 0x40];
+        data[0..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&shentsize.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&shnum.to_le_bytes());
+        data
+    }
+
+    fn note(name: &[u8], desc: &[u8], note_type: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend((name.len() as u32).to_le_bytes());
+        data.extend((desc.len() as u32).to_le_bytes());
+        data.extend(note_type.to_le_bytes());
+        data.extend(name);
+        data.resize(data.len().next_multiple_of(4), 0);
+        data.extend(desc);
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+
+    #[test]
+    fn section_header_table_out_of_bounds() {
+        let path = Path::new("test.elf");
+        for data in [
+            header(u64::MAX - 8, 0x40, 2),
+            header(0x40, 0xFFFF, 0xFFFF),
+            header(0x40, 0x40, 1),
+        ] {
+            assert!(matches!(
+                read_sections(path, &data),
+                Err(Error::InvalidElf(..))
+            ));
+        }
+        assert_eq!(read_sections(path, &header(0, 0x40, 0)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn find_build_id_in_notes() {
+        let mut notes = note(b"GNU\0", &[1, 2, 3], 1);
+        notes.extend(note(b"GNU\0", &[0xab, 0xcd], NT_GNU_BUILD_ID));
+        assert_eq!(find_build_id(&notes), Ok(Some(&[0xab, 0xcd][..])));
+        assert_eq!(find_build_id(&note(b"GNU\0", &[1], 1)), Ok(None));
+        assert_eq!(find_build_id(&[]), Ok(None));
+    }
+
+    #[test]
+    fn find_build_id_out_of_bounds() {
+        let mut huge_name = note(b"GNU\0", &[1], NT_GNU_BUILD_ID);
+        huge_name[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(find_build_id(&huge_name).is_err());
+        let mut huge_desc = note(b"GNU\0", &[1], NT_GNU_BUILD_ID);
+        huge_desc[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(find_build_id(&huge_desc).is_err());
+    }
+}
@@ -14,6 +14,8 @@ pub enum Error {
     AccessFile(String, std::io::Error),
     #[error("Cannot access directory `{0}`: {1}")]
     AccessDirectory(String, std::io::Error),
+    #[error("Invalid command line options: {0}")]
+    InvalidOption(String),
     #[error("Cannot parse config file: {0}")]
     ParseConfig(String),
     #[error(
@@ -22,18 +24,73 @@ pub enum Error {
     NoEntryPoint,
     #[error("error executing `{0}`: `{1}`: {2}")]
     Subprocess(String, String, std::io::Error),
+    #[error("Cannot compile source `{0}`: {1}")]
+    InvalidSource(String, String),
     #[error("Make failed! Check errors above.")]
     MakeError,
+    #[error("There are warnings in {0} source(s) that deny warnings! Check warnings above.")]
+    DeniedWarnings(usize),
+    #[error("Cannot parse compile commands `{0}`: {1}")]
+    InvalidCompileCommands(String, String),
     #[error("Invalid objdump output `{0}`: {1}")]
     InvalidObjdump(String, String),
+    #[error("Invalid ELF `{0}`: {1}")]
+    InvalidElf(String, String),
     #[error("Check failed! Check errors above.")]
     CheckError,
     #[error("Npdmtool failed: {0}")]
     NpdmError(ExitStatus),
+    #[error("build_romfs failed: {0}")]
+    RomfsError(ExitStatus),
+    #[error("objcopy failed: {0}")]
+    ObjcopyError(ExitStatus),
+    #[error("dwp failed: {0}")]
+    DwpError(ExitStatus),
+    #[error("Cannot find build output `{0}`. Please build the project first.")]
+    MissingOutput(String),
+    #[error("Another build is in progress (`{0}` is locked). Use `--lock-wait` to wait for it")]
+    Locked(String),
+    #[error("Deploy failed: {0}")]
+    DeployError(String),
+    #[error("The `{0}` section is missing in the config")]
+    MissingSection(String),
 }
 
 impl Error {
     pub fn print(&self) {
         errorln!("Fatal", "{}", self);
     }
+
+    /// Get the exit code of the process for this error
+    ///
+    /// - `1`: Other errors, like failing to access a file
+    /// - `2`: Invalid command line options or config, or a missing build output
+    /// - `3`: The environment is broken, like a missing tool or environment variable
+    /// - `4`: Compiling or linking failed, including warnings that deny warnings
+    /// - `5`: The check failed, or the ELF or objdump output is invalid
+    /// - `6`: Packaging failed (npdmtool, build_romfs or objcopy)
+    /// - `7`: Another build is in progress
+    /// - `8`: Deploying failed
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::AccessFile(..)
+            | Self::AccessDirectory(..)
+            | Self::Subprocess(..)
+            | Self::InvalidCompileCommands(..) => 1,
+            Self::InvalidOption(_)
+            | Self::ParseConfig(_)
+            | Self::NoEntryPoint
+            | Self::MissingSection(_)
+            | Self::MissingOutput(_) => 2,
+            Self::MissingTool(..) | Self::MissingEnv(..) => 3,
+            Self::InvalidSource(..) | Self::MakeError | Self::DeniedWarnings(_) => 4,
+            Self::CheckError | Self::InvalidObjdump(..) | Self::InvalidElf(..) => 5,
+            Self::NpdmError(_)
+            | Self::RomfsError(_)
+            | Self::ObjcopyError(_)
+            | Self::DwpError(_) => 6,
+            Self::Locked(_) => 7,
+            Self::DeployError(_) => 8,
+        }
+    }
 }
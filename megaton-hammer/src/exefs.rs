@@ -0,0 +1,129 @@
+//! Packaging the outputs into an ExeFS layout.
+//!
+//! Megaton puts the ExeFS in the `./target/megaton/<flavor>/<profile>/exefs` directory:
+//! - `<nso>`: The module NSO, named according to `exefs.nso` (e.g. `subsdk9`)
+//! - `main.npdm`: The npdm file
+//! - Other files from `exefs.files`
+//!
+//! With `--package`, the ExeFS is also archived as `./target/megaton/<flavor>/<profile>/<name>.zip`,
+//! with the files under the `exefs/` prefix, and `romfs.bin` if the RomFS is configured.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::config::Exefs;
+use crate::error::Error;
+use crate::{infoln, MegatonConfig};
+
+/// Assemble the ExeFS directory from the build outputs
+///
+/// `build_dir` is where the NSO is and `target_dir` is where `main.npdm` is.
+/// Returns the path to the ExeFS directory.
+pub fn assemble_exefs<SRoot, SBuild, STarget>(
+    root_dir: SRoot,
+    build_dir: SBuild,
+    target_dir: STarget,
+    config: &MegatonConfig,
+    exefs: &Exefs,
+) -> Result<PathBuf, Error>
+where
+    SRoot: AsRef<Path>,
+    SBuild: AsRef<Path>,
+    STarget: AsRef<Path>,
+{
+    let target_dir = target_dir.as_ref();
+    let exefs_dir = target_dir.join("exefs");
+    if exefs_dir.exists() {
+        std::fs::remove_dir_all(&exefs_dir)
+            .map_err(|e| Error::AccessDirectory(exefs_dir.display().to_string(), e))?;
+    }
+    std::fs::create_dir_all(&exefs_dir)
+        .map_err(|e| Error::AccessDirectory(exefs_dir.display().to_string(), e))?;
+
+    let output_name = config.module.output_name();
+    let nso_name = exefs.nso.as_deref().unwrap_or(output_name);
+    let nso_path = build_dir.as_ref().join(format!("{output_name}.nso"));
+    copy_to_exefs(&nso_path, &exefs_dir, nso_name)?;
+    copy_to_exefs(&target_dir.join("main.npdm"), &exefs_dir, "main.npdm")?;
+
+    for file in &exefs.files {
+        copy_to_exefs(&root_dir.as_ref().join(&file.val), &exefs_dir, &file.key)?;
+    }
+
+    infoln!("Assembled", "`{}`", exefs_dir.display());
+    Ok(exefs_dir)
+}
+
+fn copy_to_exefs(from: &Path, exefs_dir: &Path, name: &str) -> Result<(), Error> {
+    if !from.exists() {
+        return Err(Error::MissingOutput(from.display().to_string()));
+    }
+    let to = exefs_dir.join(name);
+    std::fs::copy(from, &to).map_err(|e| Error::AccessFile(to.display().to_string(), e))?;
+    Ok(())
+}
+
+/// Archive the ExeFS directory (and the packed RomFS, if any) into `<module.name>.zip` next to it
+///
+/// Returns the path to the archive.
+pub fn package_exefs<SExefs>(
+    exefs_dir: SExefs,
+    romfs_bin: Option<&Path>,
+    config: &MegatonConfig,
+) -> Result<PathBuf, Error>
+where
+    SExefs: AsRef<Path>,
+{
+    let exefs_dir = exefs_dir.as_ref();
+    let zip_path = match exefs_dir.parent() {
+        Some(parent) => parent.join(format!("{}.zip", config.module.name)),
+        None => PathBuf::from(format!("{}.zip", config.module.name)),
+    };
+    let zip_file = File::create(&zip_path)
+        .map_err(|e| Error::AccessFile(zip_path.display().to_string(), e))?;
+    let mut zip = ZipWriter::new(zip_file);
+    add_dir_to_zip(&mut zip, exefs_dir, "exefs")
+        .map_err(|e| Error::AccessFile(zip_path.display().to_string(), e))?;
+    if let Some(romfs_bin) = romfs_bin {
+        add_file_to_zip(&mut zip, romfs_bin, "romfs.bin")
+            .map_err(|e| Error::AccessFile(zip_path.display().to_string(), e))?;
+    }
+    zip.finish()
+        .map_err(|e| Error::AccessFile(zip_path.display().to_string(), e.into()))?;
+
+    let size = zip_path
+        .metadata()
+        .map_err(|e| Error::AccessFile(zip_path.display().to_string(), e))?
+        .len();
+    infoln!("Packaged", "`{}` ({} bytes)", zip_path.display(), size);
+    Ok(zip_path)
+}
+
+/// Recursively add the files in `dir` to the archive under `prefix`
+fn add_dir_to_zip(zip: &mut ZipWriter<File>, dir: &Path, prefix: &str) -> std::io::Result<()> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.add_directory(prefix, options)?;
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            add_dir_to_zip(zip, &path, &name)?;
+            continue;
+        }
+        add_file_to_zip(zip, &path, &name)?;
+    }
+    Ok(())
+}
+
+fn add_file_to_zip(zip: &mut ZipWriter<File>, path: &Path, name: &str) -> std::io::Result<()> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(name, options)?;
+    let content = std::fs::read(path)?;
+    zip.write_all(&content)
+}
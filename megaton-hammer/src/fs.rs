@@ -0,0 +1,273 @@
+//! File system helpers
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use fs2::FileExt;
+
+use crate::error::Error;
+use crate::infoln;
+
+/// Write `content` to the file at `path`, creating the parent directories if needed
+pub fn write_file<P, C>(path: P, content: C) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    C: AsRef<[u8]>,
+{
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| Error::AccessDirectory(parent.display().to_string(), e))?;
+        }
+    }
+    std::fs::write(path, content).map_err(|e| Error::AccessFile(path.display().to_string(), e))
+}
+
+/// Take the exclusive lock on `<dir>/.lock`, creating `dir` if needed
+///
+/// If another process holds the lock, this waits for it if `wait` is true,
+/// and fails otherwise. The lock is released when the returned file is dropped,
+/// or when the process exits.
+pub fn lock_dir(dir: &Path, wait: bool) -> Result<File, Error> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::AccessDirectory(dir.display().to_string(), e))?;
+    let path = dir.join(".lock");
+    let file = File::create(&path).map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    if file.try_lock_exclusive().is_ok() {
+        return Ok(file);
+    }
+    if !wait {
+        return Err(Error::Locked(path.display().to_string()));
+    }
+    infoln!(
+        "Waiting",
+        "for another build to finish (`{}`)",
+        path.display()
+    );
+    file.lock_exclusive()
+        .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    Ok(file)
+}
+
+/// Access to the file system for deciding if outputs are up to date
+///
+/// The staleness decisions take this instead of touching the disk directly,
+/// so they can be driven by an in-memory implementation.
+pub trait FileSystem {
+    /// Check if `path` exists
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Check if `path` is a directory
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// List the entries of the directory at `path`
+    fn read_dir(&self, path: &Path) -> Option<Vec<PathBuf>>;
+
+    /// Read the file at `path` as a string
+    fn read_file(&self, path: &Path) -> Result<String, Error>;
+
+    /// Get the modified time of a file, or `None` if it doesn't exist or can't be read
+    fn get_modified_time(&self, path: &Path) -> Option<SystemTime>;
+
+    /// Get the newest modified time of a directory and everything in it, recursively
+    ///
+    /// The directories themselves are included, so removing a file also counts as a modification.
+    fn get_newest_modified_time(&self, path: &Path) -> Option<SystemTime> {
+        let mut newest = self.get_modified_time(path)?;
+        if self.is_dir(path) {
+            for entry in self.read_dir(path)? {
+                if let Some(time) = self.get_newest_modified_time(&entry) {
+                    newest = newest.max(time);
+                }
+            }
+        }
+        Some(newest)
+    }
+
+    /// Check if `output` exists and is not older than `input_time`
+    fn is_up_to_date(&self, output: &Path, input_time: Option<SystemTime>) -> bool {
+        match self.get_modified_time(output) {
+            Some(output_time) => input_time <= Some(output_time),
+            None => false,
+        }
+    }
+}
+
+/// The real file system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read_dir(&self, path: &Path) -> Option<Vec<PathBuf>> {
+        let entries = std::fs::read_dir(path).ok()?;
+        Some(entries.flatten().map(|entry| entry.path()).collect())
+    }
+
+    fn read_file(&self, path: &Path) -> Result<String, Error> {
+        std::fs::read_to_string(path).map_err(|e| Error::AccessFile(path.display().to_string(), e))
+    }
+
+    fn get_modified_time(&self, path: &Path) -> Option<SystemTime> {
+        if !path.exists() {
+            return None;
+        }
+        path.metadata().and_then(|m| m.modified()).ok()
+    }
+}
+
+/// An in-memory file system for testing the staleness decisions
+///
+/// A path is a directory if it was added with [`dir`](Self::dir). The entries of a
+/// directory are the paths added directly in it.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    /// The content (`None` for directories) and modified time of each path
+    entries: std::collections::BTreeMap<PathBuf, (Option<String>, SystemTime)>,
+}
+
+#[cfg(test)]
+impl MemoryFileSystem {
+    /// Add or replace a file, modified `secs` seconds after the Unix epoch
+    pub fn file(mut self, path: impl AsRef<Path>, content: &str, secs: u64) -> Self {
+        self.entries.insert(
+            path.as_ref().to_path_buf(),
+            (Some(content.to_string()), Self::time(secs)),
+        );
+        self
+    }
+
+    /// Add or replace a directory, modified `secs` seconds after the Unix epoch
+    pub fn dir(mut self, path: impl AsRef<Path>, secs: u64) -> Self {
+        self.entries
+            .insert(path.as_ref().to_path_buf(), (None, Self::time(secs)));
+        self
+    }
+
+    fn time(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs)
+    }
+}
+
+#[cfg(test)]
+impl FileSystem for MemoryFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some((None, _)))
+    }
+
+    fn read_dir(&self, path: &Path) -> Option<Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            return None;
+        }
+        let entries = self
+            .entries
+            .keys()
+            .filter(|entry| entry.parent() == Some(path))
+            .cloned()
+            .collect();
+        Some(entries)
+    }
+
+    fn read_file(&self, path: &Path) -> Result<String, Error> {
+        match self.entries.get(path) {
+            Some((Some(content), _)) => Ok(content.clone()),
+            _ => Err(Error::AccessFile(
+                path.display().to_string(),
+                std::io::ErrorKind::NotFound.into(),
+            )),
+        }
+    }
+
+    fn get_modified_time(&self, path: &Path) -> Option<SystemTime> {
+        self.entries.get(path).map(|(_, time)| *time)
+    }
+}
+
+/// Create an empty directory in the temp directory for a test
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("megaton-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Get the modified time of a file, or `None` if it doesn't exist or can't be read
+pub fn get_modified_time(path: &Path) -> Option<SystemTime> {
+    RealFileSystem.get_modified_time(path)
+}
+
+/// Get the newest modified time of a directory and everything in it, recursively
+pub fn get_newest_modified_time(path: &Path) -> Option<SystemTime> {
+    RealFileSystem.get_newest_modified_time(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(secs: u64) -> Option<SystemTime> {
+        Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn is_up_to_date_compares_with_input_time() {
+        let fs = MemoryFileSystem::default().file("out", "", 10);
+        assert!(fs.is_up_to_date(Path::new("out"), time(9)));
+        assert!(fs.is_up_to_date(Path::new("out"), time(10)));
+        assert!(!fs.is_up_to_date(Path::new("out"), time(11)));
+        assert!(fs.is_up_to_date(Path::new("out"), None));
+        assert!(!fs.is_up_to_date(Path::new("missing"), None));
+    }
+
+    #[test]
+    fn newest_modified_time_includes_nested_entries() {
+        let fs = MemoryFileSystem::default()
+            .dir("romfs", 5)
+            .file("romfs/a.txt", "a", 7)
+            .dir("romfs/sub", 6)
+            .file("romfs/sub/b.txt", "b", 12)
+            .file("other.txt", "", 20);
+        assert_eq!(fs.get_newest_modified_time(Path::new("romfs")), time(12));
+        assert_eq!(
+            fs.get_newest_modified_time(Path::new("romfs/sub")),
+            time(12)
+        );
+        assert_eq!(
+            fs.get_newest_modified_time(Path::new("romfs/a.txt")),
+            time(7)
+        );
+        assert_eq!(fs.get_newest_modified_time(Path::new("missing")), None);
+    }
+
+    #[test]
+    fn write_file_creates_parent_directories() {
+        let dir = test_dir("write-file");
+        let path = dir.join("a/b/c/file.txt");
+        write_file(&path, "content").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "content");
+        // existing parents are fine
+        write_file(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        // a file in the way of a parent is an error
+        assert!(matches!(
+            write_file(path.join("file.txt"), ""),
+            Err(Error::AccessFile(..))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
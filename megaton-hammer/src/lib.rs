@@ -1,21 +1,38 @@
 use std::{
     path::{Path, PathBuf},
-    process::{Command, Stdio},
-    time::SystemTime,
+    time::Instant,
 };
 
 use clap::{Parser, Subcommand};
 
 pub mod config;
 pub use config::MegatonConfig;
+pub mod bench;
 pub mod check;
+pub mod debug;
+pub mod deploy;
+pub mod diagnostic;
+pub mod elf;
+pub mod exefs;
+pub mod lint;
 pub mod make;
+pub mod manifest;
+pub mod npdm;
+pub mod objdump;
+pub mod romfs;
+pub mod sarif;
+pub mod size;
+pub mod symbols;
 
 pub mod error;
 use error::Error;
 
+pub mod fs;
+
 pub mod print;
 
+pub mod process;
+
 /// CLI entry point
 #[derive(Debug, Clone, Default, PartialEq, Parser)]
 #[command(author, version, about)]
@@ -23,8 +40,16 @@ pub struct MegatonHammer {
     /// The project directory.
     ///
     /// If specified, megaton will run as if invoked from this directory.
-    #[clap(short('C'), long, default_value = ".")]
-    pub dir: String,
+    /// Defaults to the current directory.
+    #[clap(short('C'), long)]
+    pub dir: Option<String>,
+
+    /// Path to the Megaton.toml to use.
+    ///
+    /// The project directory is the directory containing the file.
+    /// Use `-` to read the config from stdin, in which case `-C` is required.
+    #[clap(long)]
+    pub manifest_path: Option<String>,
 
     /// The subcommand
     #[clap(subcommand)]
@@ -39,6 +64,80 @@ pub struct MegatonHammer {
 pub enum MegatonCommand {
     /// Remove the outputs
     Clean,
+    /// Assemble the ExeFS from the outputs of the last build, without building
+    Exefs,
+    /// Build, then copy or upload the outputs according to the `deploy` section
+    ///
+    /// The ExeFS files are deployed if the `exefs` section exists, otherwise the NSO
+    /// and the npdm are. Requires `nso` in `--emit`.
+    Deploy,
+    /// Clean and build repeatedly, then print the min, median and max times
+    ///
+    /// The output of the builds is hidden. This refuses to run if the project has
+    /// uncommitted changes, so benchmarks are comparable, unless `--force` is given.
+    Bench {
+        /// The number of timed runs
+        #[clap(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+        runs: u32,
+        /// The number of runs before the timed runs, which are discarded
+        #[clap(long, default_value_t = 1)]
+        warmup: u32,
+        /// Run even if the project has uncommitted changes
+        #[clap(long)]
+        force: bool,
+    },
+    /// Check the ELF without building
+    Check {
+        /// Check this ELF instead of the one from the last build.
+        ///
+        /// The `check` section in Megaton.toml is still used for the symbols and ignores.
+        #[clap(long)]
+        elf: Option<String>,
+    },
+    /// Print the GNU build-id of the ELF from the last build
+    BuildId {
+        /// Print the build-id of this ELF instead of the one from the last build.
+        #[clap(long)]
+        elf: Option<String>,
+    },
+    /// Print the section sizes of the ELF from the last build
+    ///
+    /// The difference from the last time this command ran is also printed.
+    Size {
+        /// Print the sizes of this ELF instead of the one from the last build.
+        #[clap(long)]
+        elf: Option<String>,
+    },
+    /// Report config entries that the last build didn't use
+    ///
+    /// Include directories that no compiled source included a header from are reported.
+    LintConfig,
+    /// Print the JSON schema of Megaton.toml, for editor completion and validation
+    Schema,
+    /// Print the compile command of a source as a compile_commands.json entry, without building
+    CompileCommand {
+        /// The source, relative to Megaton.toml
+        source: String,
+    },
+    /// Inspect dynamic symbol sets
+    Symbols {
+        #[clap(subcommand)]
+        command: SymbolsCommand,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum SymbolsCommand {
+    /// Print the difference between the dynamic symbols of two ELFs or `.syms` files
+    Diff {
+        /// The base ELF or `.syms` file
+        a: String,
+        /// The ELF or `.syms` file to compare against the base
+        b: String,
+        /// Print the result as JSON
+        #[clap(long)]
+        json: bool,
+    },
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Parser)]
@@ -55,19 +154,193 @@ pub struct BuildOptions {
     /// Different profiles for `cargo`, `make` and `check` can be defined
     /// in the Megaton.toml file under `cargo.profiles`, `make.profiles` and
     /// `check.profiles` respectively.
-    #[clap(short, long, default_value = "none")]
-    pub profile: String,
+    ///
+    /// If not specified, `MEGATON_PROFILE` is used, then `default-profile` in the
+    /// `module` section, then `none`.
+    #[clap(short, long, env = "MEGATON_PROFILE")]
+    pub profile: Option<String>,
 
     /// Suppress output
     #[clap(short, long)]
     pub quiet: bool,
+
+    /// Print more information for diagnosing problems
+    ///
+    /// `-v` prints the tools and commands being run, `-vv` also prints the
+    /// environment of child processes, full compiler commands and modified time comparisons.
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Print every command that is run, and its exit status and duration
+    ///
+    /// Unlike `--verbose`, nothing else is printed, so the commands are easy to
+    /// find and run again by hand.
+    #[clap(long)]
+    pub trace_exec: bool,
+
+    /// Archive the ExeFS into `<name>.zip` after it's assembled.
+    ///
+    /// The ExeFS is assembled even if there is no `exefs` section in the config.
+    #[clap(long)]
+    pub package: bool,
+
+    /// Write a SARIF report of the check findings to this path.
+    ///
+    /// The report also has the compiler diagnostics. GCC prints them as JSON
+    /// when this is set, so they are parsed reliably.
+    #[clap(long)]
+    pub sarif: Option<String>,
+
+    /// Only compile the sources listed in this file, one per line.
+    ///
+    /// The paths are relative to Megaton.toml. The module is linked
+    /// from the objects that already exist in the build directory.
+    #[clap(long)]
+    pub sources_from: Option<String>,
+
+    /// Only compile the sources changed since this git revision, then relink.
+    ///
+    /// Sources that include a changed header are also compiled. Changes to the
+    /// config or flags are not detected, so a full build is still needed for those.
+    #[clap(long, conflicts_with = "sources_from")]
+    pub since: Option<String>,
+
+    /// Explain why this source would be compiled, instead of building.
+    ///
+    /// The path is relative to Megaton.toml. This prints the reasons from
+    /// the last build's outputs, like a header that is newer than the object.
+    #[clap(long, conflicts_with_all = ["sources_from", "since"])]
+    pub explain_rebuild: Option<String>,
+
+    /// Preprocess this source with the flags of the build, instead of building.
+    ///
+    /// The path is relative to Megaton.toml. The output of `-E` is written to
+    /// `<stem>.i` in the build directory. The project must have been built before.
+    #[clap(long, conflicts_with_all = ["sources_from", "since", "explain_rebuild"])]
+    pub dump_preprocessed: Option<String>,
+
+    /// The outputs to build, separated by commas.
+    ///
+    /// With only `elf`, the build stops after linking and checking the ELF,
+    /// without creating the NSO, npdm or ExeFS.
+    #[clap(long, value_enum, value_delimiter = ',', default_value = "nso")]
+    pub emit: Vec<Emit>,
+
+    /// Allow `--sources-from` to list sources outside of the source directories.
+    #[clap(long)]
+    pub allow_external: bool,
+
+    /// Print the config with the profile applied as TOML, instead of running the command
+    #[clap(long)]
+    pub print_config: bool,
+
+    /// Wait for another build of the same profile to finish instead of failing
+    #[clap(long)]
+    pub lock_wait: bool,
+
+    /// Override a config value, like `make.entry=my_main`. Can be repeated.
+    ///
+    /// The key is a dotted path in Megaton.toml, including profiles like
+    /// `make.profiles.foo.defines`. The value is parsed as TOML (like `true`, `0x10`
+    /// or `["A", "B"]`), or used as a string if it's not valid TOML. The overrides replace
+    /// the values in the file before the profile is applied, so a profile still extends
+    /// or overrides them.
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub config_override: Vec<String>,
+
+    /// The number of threads for loading symbols in the check.
+    ///
+    /// Loading is mostly waiting for files and objdump, so by default every
+    /// symbol file and reference binary is loaded in its own thread. Compiling
+    /// always uses one job per CPU.
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub io_jobs: Option<u16>,
+}
+
+/// An output of the build for `--emit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Emit {
+    /// The linked (and checked) ELF
+    Elf,
+    /// The NSO and everything after it (the default)
+    Nso,
 }
 
 impl MegatonHammer {
     /// Invoke `self.command`
     pub fn invoke(&self) -> Result<(), Error> {
+        print::set_verbose_level(self.options.verbose);
+        process::set_trace_exec(self.options.trace_exec);
+        match (&self.manifest_path, &self.dir) {
+            (Some(manifest_path), None) if manifest_path == "-" => {
+                return Err(Error::InvalidOption(
+                    "`-C` is required when reading the config from stdin".to_string(),
+                ));
+            }
+            (Some(manifest_path), Some(_)) if manifest_path != "-" => {
+                return Err(Error::InvalidOption(
+                    "`--manifest-path` cannot be used with `-C`".to_string(),
+                ));
+            }
+            (Some(manifest_path), _)
+                if manifest_path != "-"
+                    && Path::new(manifest_path).file_name() != Some("Megaton.toml".as_ref()) =>
+            {
+                hintln!("Warning", "`{}` is not named `Megaton.toml`", manifest_path);
+            }
+            _ => {}
+        }
+        if self.options.profile.is_none() {
+            // commands that don't need the config still work if it can't be loaded
+            if let Ok(config) = self.load_config() {
+                return self
+                    .with_default_profile(config.module.default_profile)
+                    .invoke_command();
+            }
+        }
+        self.invoke_command()
+    }
+
+    /// Use `default_profile` if the profile is not set with `--profile` or `MEGATON_PROFILE`
+    fn with_default_profile(&self, default_profile: Option<String>) -> Self {
+        let mut cli = self.clone();
+        if cli.options.profile.is_none() {
+            cli.options.profile = default_profile;
+        }
+        cli
+    }
+
+    /// Invoke the subcommand, after the options are validated
+    fn invoke_command(&self) -> Result<(), Error> {
+        if self.options.print_config {
+            return self.print_config();
+        }
+        if let Some(source) = &self.options.dump_preprocessed {
+            return self.dump_preprocessed(source);
+        }
         match &self.command {
             Some(MegatonCommand::Clean) => self.clean(),
+            Some(MegatonCommand::Exefs) => self.exefs(),
+            Some(MegatonCommand::Deploy) => self.deploy(),
+            Some(MegatonCommand::Bench {
+                runs,
+                warmup,
+                force,
+            }) => self.bench(*runs, *warmup, *force),
+            Some(MegatonCommand::Check { elf }) => self.check(elf.as_deref()),
+            Some(MegatonCommand::BuildId { elf }) => self.build_id(elf.as_deref()),
+            Some(MegatonCommand::Size { elf }) => self.size(elf.as_deref()),
+            Some(MegatonCommand::LintConfig) => self.lint_config(),
+            Some(MegatonCommand::Schema) => self.schema(),
+            Some(MegatonCommand::CompileCommand { source }) => {
+                let command = self.dry_compile_command(source)?;
+                match serde_json::to_string_pretty(&command) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => errorln!("Error", "Failed to serialize compiler command: {}", e),
+                }
+                Ok(())
+            }
+            Some(MegatonCommand::Symbols { command }) => self.symbols(command),
             None => self.build(),
         }
     }
@@ -77,77 +350,112 @@ impl MegatonHammer {
         {
             warnln!("Warning", "You are using Windows. There is a high chance the tool does not work. Please consider using WSL or a Linux environment to save yourself from troubles.");
         }
-        which::which("make").map_err(|_| {
+        let make = which::which("make").map_err(|_| {
             Error::MissingTool(
                 "make".to_string(),
                 "Please ensure it is installed in the system.".to_string(),
             )
         })?;
 
-        let env_dev_kit_pro = std::env::var("DEVKITPRO").unwrap_or_default();
-        if env_dev_kit_pro.is_empty() {
-            return Err(Error::MissingEnv(
-                "DEVKITPRO".to_string(),
-                "Please ensure devkitPro is installed in the system.".to_string(),
-            ));
-        }
-        let npdmtool = Path::new(&env_dev_kit_pro).join("tools/bin/npdmtool");
-        if which::which(&npdmtool).is_err() {
-            return Err(Error::MissingTool(
-                "npdmtool".to_string(),
-                "Please ensure devkitPro is installed in the system.".to_string(),
-            ));
-        }
-        let objdump = Path::new(&env_dev_kit_pro).join("devkitA64/bin/aarch64-none-elf-objdump");
-        if which::which(&objdump).is_err() {
-            return Err(Error::MissingTool(
-                "aarch64-none-elf-objdump".to_string(),
-                "Please ensure devkitPro is installed in the system.".to_string(),
-            ));
-        }
+        let env_dev_kit_pro = devkitpro()?;
 
-        let mut dkp_bin_path = Path::new(&env_dev_kit_pro).join("devkitA64/bin").display().to_string();
+        let mut dkp_bin_path = Path::new(&env_dev_kit_pro)
+            .join("devkitA64/bin")
+            .display()
+            .to_string();
         if !dkp_bin_path.ends_with('/') {
             dkp_bin_path.push('/');
         }
 
-        let root_dir = Path::new(&self.dir);
-        let megaton_toml_path = root_dir.join("Megaton.toml");
+        if print::verbose_level() >= 1 {
+            print_tool_resolution("make", &make);
+            for tool in ["aarch64-none-elf-gcc", "aarch64-none-elf-g++"] {
+                print_tool_resolution(tool, &Path::new(&dkp_bin_path).join(tool));
+            }
+        }
+
+        let emit_nso = self.options.emit.contains(&Emit::Nso);
+        if !emit_nso && self.options.package {
+            return Err(Error::InvalidOption(
+                "`--package` requires `nso` in `--emit`".to_string(),
+            ));
+        }
+
+        let root_dir = &self.root_dir();
+        let megaton_toml_path = self.manifest_path();
         infoln!("Loading", "{}", megaton_toml_path.display());
-        let config = MegatonConfig::from_path(&megaton_toml_path)?;
-        let flavor = if self.options.release {
-            "release"
-        } else {
-            "debug"
-        };
-        let profile = &self.options.profile;
+        let config = self.load_config()?;
+        if let Some(message) = config.module.check_title_id() {
+            hintln!("Warning", "{}", message);
+            hintln!(
+                "Hint",
+                "Set `allow-any-title-id = true` in the `module` section to suppress this warning."
+            );
+        }
+        let flavor = self.flavor();
+        let profile = self.profile();
+        if config.make.get_profile(profile).compiler == Some(config::Compiler::Clang) {
+            for tool in ["clang", "clang++"] {
+                let path = which::which(tool).map_err(|_| {
+                    Error::MissingTool(
+                        tool.to_string(),
+                        "It is required when `compiler` is `clang`.".to_string(),
+                    )
+                })?;
+                if print::verbose_level() >= 1 {
+                    print_tool_resolution(tool, &path);
+                }
+            }
+        }
 
         infoln!(
             "Building",
             "{} ({flavor}, profile `{profile}`)",
             config.module.name
         );
-        let target_dir = root_dir.join("target/megaton").join(flavor).join(profile);
-        let makefile = config.create_makefile(&self)?;
+        if config.module.output_name() != config.module.name {
+            infoln!("Output", "{}.nso", config.module.output_name());
+        }
+        let target_dir = self.profile_target_dir();
+        // builds of the same profile share outputs, so only one can run at a time
+        let _lock = fs::lock_dir(&target_dir, self.options.lock_wait)?;
         let make_dir = target_dir.join("make");
         let build_dir = make_dir.join("build");
+        let makefile = config.create_makefile(self, &build_dir)?;
         let makefile_path = make_dir.join("build.mk");
-        let mut need_new_makefile = true;
-        if makefile_path.exists() {
-            if let Ok(old_makefile) = std::fs::read_to_string(&makefile_path) {
-                if old_makefile == makefile {
-                    need_new_makefile = false;
-                }
+        let makefile_changed =
+            make::is_makefile_changed(&fs::RealFileSystem, &makefile_path, &makefile);
+        if let Some(source) = &self.options.explain_rebuild {
+            let make_config = config.make.get_profile(profile);
+            let reasons = if makefile_changed {
+                vec![
+                    "the Makefile changed (like the config or flags), so every source is compiled"
+                        .to_string(),
+                ]
+            } else {
+                make::explain_rebuild(
+                    root_dir,
+                    &build_dir,
+                    source,
+                    &make_config.extra_source_types,
+                    make_config.hash_based.unwrap_or_default(),
+                )?
+            };
+            if reasons.is_empty() {
+                infoln!("Fresh", "`{}` is up to date", source);
+            }
+            for reason in reasons {
+                infoln!("Rebuild", "`{}`: {}", source, reason);
             }
+            return Ok(());
         }
-        if need_new_makefile {
+        if makefile_changed {
             if !make_dir.exists() {
                 std::fs::create_dir_all(&make_dir)
                     .map_err(|e| Error::AccessDirectory(make_dir.display().to_string(), e))?;
                 infoln!("Created", "`{}`", make_dir.display());
             }
-            std::fs::write(&makefile_path, makefile)
-                .map_err(|e| Error::AccessDirectory(makefile_path.display().to_string(), e))?;
+            fs::write_file(&makefile_path, makefile)?;
             infoln!("Saved", "`{}`", makefile_path.display());
             if build_dir.exists() {
                 std::fs::remove_dir_all(&build_dir)
@@ -160,71 +468,697 @@ impl MegatonHammer {
             infoln!("Created", "`{}`", build_dir.display());
         }
 
+        let make_config = config.make.get_profile(profile);
+        if make_config.respect_gitignore.unwrap_or_default() {
+            make::write_source_list(
+                root_dir,
+                &make_dir,
+                &make_config.sources,
+                &make_config.extra_source_types,
+            )?;
+        }
+        let mut ld_scripts = make_config
+            .ld_scripts
+            .iter()
+            .map(|script| root_dir.join(script))
+            .collect::<Vec<_>>();
+        ld_scripts.extend(make::generate_ld_scripts(
+            root_dir,
+            &make_dir,
+            &target_dir,
+            &config,
+            &make_config,
+        )?);
+        make::write_link_stamp(&build_dir, &ld_scripts)?;
+
+        // the manifest is only for successful builds, so a stale one is removed first
+        let manifest_path = target_dir.join("manifest.json");
+        if manifest_path.exists() {
+            std::fs::remove_file(&manifest_path)
+                .map_err(|e| Error::AccessFile(manifest_path.display().to_string(), e))?;
+        }
+
         // build ELF
+        let phase_start = Instant::now();
         let elf_target = format!("{}.elf", config.module.name);
         let elf_path = build_dir.join(&elf_target);
-        let elf_modified_time = get_modified_time(&elf_path);
+        // objects of deleted sources would be linked by the builds that link every object
+        let removed = make::remove_stale_objects(&build_dir)?;
+        if removed > 0 {
+            infoln!("Removed", "{} object(s) of deleted sources", removed);
+            // make only relinks when a prerequisite is newer, so the ELF is removed
+            // to relink without the objects
+            if elf_path.exists() {
+                std::fs::remove_file(&elf_path)
+                    .map_err(|e| Error::AccessFile(elf_path.display().to_string(), e))?;
+            }
+        }
+        let elf_modified_time = fs::get_modified_time(&elf_path);
         if elf_modified_time.is_none() && elf_path.exists() {
             std::fs::remove_file(&elf_path)
                 .map_err(|e| Error::AccessFile(elf_path.display().to_string(), e))?;
         }
-        make::invoke_make(
-            &root_dir,
+        let deny_warnings_in =
+            make::compile_globs("deny-warnings-in", &make_config.deny_warnings_in)?;
+        let hash_based = make_config.hash_based.unwrap_or_default();
+        if hash_based {
+            make::refresh_unchanged_objects(&build_dir)?;
+        }
+        // the known symbols don't depend on the module, so they are loaded while compiling.
+        // They are only needed if the ELF is linked again, which make is asked first
+        let preload = match &config.check {
+            Some(check_config)
+                if !make::is_target_up_to_date(&build_dir, "../build.mk", &elf_target, &[])? =>
+            {
+                let check = check_config.get_profile(profile);
+                let root_dir = root_dir.to_path_buf();
+                let objdump = devkitpro_tool(
+                    &env_dev_kit_pro,
+                    "devkitA64/bin",
+                    "aarch64-none-elf-objdump",
+                );
+                let io_jobs = self.options.io_jobs;
+                Some(check::PreloadedSymbols::spawn(move || {
+                    check::load_symbols(&root_dir, &objdump?, &check, io_jobs)
+                }))
+            }
+            _ => None,
+        };
+        let mut diagnostics = Vec::new();
+        let mut link_args = Vec::new();
+        // the structured diagnostics are only needed for the SARIF report, and clang
+        // doesn't have the JSON format
+        let diagnostics_args = if self.options.sarif.is_some()
+            && make_config.compiler.unwrap_or_default() == config::Compiler::Gcc
+        {
+            vec![format!(
+                "DIAGNOSTICS_FLAGS={}",
+                diagnostic::JSON_FORMAT_FLAG
+            )]
+        } else {
+            Vec::new()
+        };
+        if let Some(sources_from) = &self.options.sources_from {
+            let (objects, external_dirs) = make::read_sources_list(
+                root_dir,
+                Path::new(sources_from),
+                &make_config.sources,
+                &make_config.extra_source_types,
+                self.options.allow_external,
+            )?;
+            let mut compile_args = diagnostics_args.clone();
+            if !external_dirs.is_empty() {
+                compile_args.push(format!("--eval=VPATH := {}", external_dirs.join(" ")));
+            }
+            diagnostics = make::invoke_make(
+                root_dir,
+                &build_dir,
+                "../build.mk",
+                &objects,
+                &compile_args,
+                &dkp_bin_path,
+                true,
+            )?;
+            // link whatever objects exist instead of building all of them
+            link_args.push("OFILES=$(wildcard *.o)".to_string());
+        }
+        if let Some(since) = &self.options.since {
+            let objects = make::objects_changed_since(
+                root_dir,
+                &build_dir,
+                since,
+                &make_config.sources,
+                &make_config.extra_source_types,
+            )?;
+            infoln!("Changed", "{} source(s) since `{}`", objects.len(), since);
+            if !objects.is_empty() {
+                diagnostics = make::invoke_make(
+                    root_dir,
+                    &build_dir,
+                    "../build.mk",
+                    &objects,
+                    &diagnostics_args,
+                    &dkp_bin_path,
+                    true,
+                )?;
+            }
+            link_args.push("OFILES=$(wildcard *.o)".to_string());
+        }
+        diagnostics.extend(make::invoke_make(
+            root_dir,
             &build_dir,
             "../build.mk",
-            &elf_target,
+            std::slice::from_ref(&elf_target),
+            &[link_args.as_slice(), diagnostics_args.as_slice()].concat(),
             &dkp_bin_path,
             true,
-        )?;
-        let new_elf_modified_time = get_modified_time(&elf_path);
+        )?);
+        if let Err(e) = make::check_denied_warnings(&build_dir, &diagnostics, &deny_warnings_in) {
+            // the ELF is already linked from the objects with the warnings
+            if elf_path.exists() {
+                std::fs::remove_file(&elf_path)
+                    .map_err(|e| Error::AccessFile(elf_path.display().to_string(), e))?;
+            }
+            return Err(e);
+        }
+        if hash_based {
+            make::save_source_hashes(&build_dir)?;
+        }
+        let new_elf_modified_time = fs::get_modified_time(&elf_path);
         if new_elf_modified_time.is_none() {
             return Err(Error::MakeError);
         }
+        bench::record_phase("compile", phase_start.elapsed());
+        let phase_start = Instant::now();
+        verboseln!(
+            2,
+            "Modified",
+            "{}: {:?} -> {:?}",
+            elf_target,
+            elf_modified_time,
+            new_elf_modified_time
+        );
         if new_elf_modified_time != elf_modified_time {
             if let Some(check_config) = &config.check {
                 let check = check_config.get_profile(profile);
-                check::check_symbols(root_dir, &elf_path, &objdump, &check)?;
+                let sarif = self.options.sarif.as_ref().map(Path::new);
+                let result = devkitpro_tool(
+                    &env_dev_kit_pro,
+                    "devkitA64/bin",
+                    "aarch64-none-elf-objdump",
+                )
+                .and_then(|objdump| {
+                    if print::verbose_level() >= 1 {
+                        print_tool_resolution("aarch64-none-elf-objdump", &objdump);
+                    }
+                    check::check_with_validators(
+                        root_dir,
+                        &elf_path,
+                        &check.external_validators,
+                        || {
+                            check::check_symbols(
+                                &elf_path,
+                                &objdump,
+                                &check,
+                                || match preload {
+                                    Some(preload) => preload.join(),
+                                    None => check::load_symbols(
+                                        root_dir,
+                                        &objdump,
+                                        &check,
+                                        self.options.io_jobs,
+                                    ),
+                                },
+                                sarif.map(|sarif| (sarif, diagnostics.as_slice())),
+                            )?;
+                            if check.strict_exports.unwrap_or_default() {
+                                check::check_exports(
+                                    &elf_path,
+                                    &objdump,
+                                    &build_dir.join("verfile"),
+                                    &self.report_dir(&check).join("exports.txt"),
+                                )?;
+                            }
+                            Ok(())
+                        },
+                    )
+                });
+                // if only the ELF is emitted, it's the output, so it's only removed if the check failed
+                remove_checked_elf(
+                    &elf_path,
+                    result.is_err(),
+                    check.rename_on_fail.unwrap_or(true),
+                    !emit_nso,
+                )?;
+                result?;
             }
         }
 
-        let nso_target = format!("{}.nso", config.module.name);
-        make::invoke_make(
-            &root_dir,
-            &build_dir,
-            "../build.mk",
-            &nso_target,
-            &dkp_bin_path,
-            false,
-        )?;
+        if config.check.is_some() {
+            bench::record_phase("check", phase_start.elapsed());
+        } else if let Some(sarif) = &self.options.sarif {
+            // without the check, the report only has the compiler diagnostics
+            sarif::write_sarif(sarif, &elf_path, &[], &diagnostics)?;
+            infoln!("Saved", "`{}`", sarif);
+        }
+        let phase_start = Instant::now();
 
-        let app_json_path = target_dir.join("npdm-app.json");
-        let app_json = include_str!("./template.json")
-            .replace("TITLE_ID_PLACEHOLDER", &config.module.title_id_hex());
-        std::fs::write(&app_json_path, app_json)
-            .map_err(|e| Error::AccessFile(app_json_path.display().to_string(), e))?;
-
-        let args = vec![
-            app_json_path.display().to_string(),
-            target_dir.join("main.npdm").display().to_string(),
-        ];
-        let command = format!("{} {}", npdmtool.display().to_string(), args.join(" "));
-        let mut child = Command::new(npdmtool)
-            .args(&args)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()
-            .map_err(|e| Error::Subprocess(command.clone(), "cannot spawn child".to_string(), e))?;
-        let status = child.wait().map_err(|e| {
-            Error::Subprocess(command.clone(), "cannot wait for child".to_string(), e)
-        })?;
-        if !status.success() {
-            return Err(Error::NpdmError(status));
+        let (main_output, extensions) = if emit_nso {
+            let nso_target = format!("{}.nso", config.module.name);
+            make::invoke_make(
+                root_dir,
+                &build_dir,
+                "../build.mk",
+                &[nso_target],
+                &link_args,
+                &dkp_bin_path,
+                false,
+            )?;
+            ("nso", &["elf", "nso", "map"][..])
+        } else {
+            ("elf", &["elf", "map"][..])
+        };
+
+        // the linker names the module after the output file, so the outputs are
+        // built with the module name and copied to the output name afterwards
+        let output_name = config.module.output_name();
+        let mut artifacts = extensions
+            .iter()
+            .map(|extension| {
+                (
+                    *extension,
+                    build_dir.join(format!("{output_name}.{extension}")),
+                )
+            })
+            .collect::<Vec<_>>();
+        if output_name != config.module.name {
+            for extension in extensions {
+                let from = build_dir.join(format!("{}.{extension}", config.module.name));
+                let to = build_dir.join(format!("{output_name}.{extension}"));
+                std::fs::copy(&from, &to)
+                    .map_err(|e| Error::AccessFile(from.display().to_string(), e))?;
+            }
+            infoln!("Copied", "{output_name}.{main_output}");
+        }
+
+        if make_config.split_debug.unwrap_or_default() {
+            let objcopy = devkitpro_tool(
+                &env_dev_kit_pro,
+                "devkitA64/bin",
+                "aarch64-none-elf-objcopy",
+            )?;
+            let debug_path = target_dir.join(format!("{output_name}.debug"));
+            debug::extract_debug_info(&objcopy, &elf_path, &debug_path)?;
+            artifacts.push(("debug", debug_path));
+            let dwp = devkitpro_tool(&env_dev_kit_pro, "devkitA64/bin", "aarch64-none-elf-dwp")?;
+            let dwp_path = target_dir.join(format!("{output_name}.dwp"));
+            debug::package_split_dwarf(&dwp, &elf_path, &dwp_path)?;
+            artifacts.push(("dwp", dwp_path));
+        }
+
+        match elf::read_build_id(&elf_path)? {
+            Some(build_id) => {
+                let build_id_path = target_dir.join(format!("{output_name}.build-id"));
+                fs::write_file(&build_id_path, format!("{build_id}\n"))?;
+                infoln!("Build ID", "{}", build_id);
+                artifacts.push(("build-id", build_id_path));
+            }
+            None => {
+                hintln!("Warning", "`{}` has no build-id", elf_path.display());
+            }
         }
-        infoln!("Created", "main.npdm");
+
+        if !emit_nso {
+            manifest::write_manifest(&target_dir, &config, flavor, profile, &artifacts)?;
+            bench::record_phase("package", phase_start.elapsed());
+            return Ok(());
+        }
+
+        // the npdm is only required for the ExeFS, so the build can continue without npdmtool
+        let need_exefs = config.exefs.is_some() || self.options.package;
+        match devkitpro_tool(&env_dev_kit_pro, "tools/bin", "npdmtool") {
+            Ok(npdmtool) => {
+                if print::verbose_level() >= 1 {
+                    print_tool_resolution("npdmtool", &npdmtool);
+                }
+                npdm::generate_npdm(npdmtool, &target_dir, &config)?;
+                artifacts.push(("npdm", target_dir.join("main.npdm")));
+            }
+            Err(e) if !need_exefs => {
+                hintln!("Warning", "{}", e);
+                hintln!("Warning", "Skipped generating main.npdm");
+            }
+            Err(e) => return Err(e),
+        }
+
+        let romfs_bin = match &config.romfs {
+            Some(romfs) => {
+                let build_romfs = devkitpro_tool(&env_dev_kit_pro, "tools/bin", "build_romfs")?;
+                Some(romfs::build_romfs(
+                    root_dir,
+                    &target_dir,
+                    build_romfs,
+                    romfs,
+                )?)
+            }
+            None => None,
+        };
+
+        if let Some(romfs_bin) = &romfs_bin {
+            artifacts.push(("romfs", romfs_bin.clone()));
+        }
+
+        if need_exefs {
+            let exefs = config.exefs.clone().unwrap_or_default();
+            let exefs_dir =
+                exefs::assemble_exefs(root_dir, &build_dir, &target_dir, &config, &exefs)?;
+            if self.options.package {
+                let zip_path = exefs::package_exefs(exefs_dir, romfs_bin.as_deref(), &config)?;
+                artifacts.push(("zip", zip_path));
+            }
+        }
+        manifest::write_manifest(&target_dir, &config, flavor, profile, &artifacts)?;
+        bench::record_phase("package", phase_start.elapsed());
 
         Ok(())
     }
 
+    /// Invoke the bench command
+    pub fn bench(&self, runs: u32, warmup: u32, force: bool) -> Result<(), Error> {
+        let root_dir = self.root_dir();
+        if !force {
+            match make::uncommitted_changes(&root_dir) {
+                Some(0) => {}
+                Some(count) => {
+                    return Err(Error::InvalidOption(format!(
+                        "there are {count} uncommitted change(s). Commit them or use `--force`"
+                    )))
+                }
+                None => {
+                    hintln!(
+                        "Warning",
+                        "Cannot check for uncommitted changes, since the project is not in a git repository"
+                    );
+                }
+            }
+        }
+        let mut results = Vec::new();
+        for i in 0..warmup + runs {
+            let is_warmup = i < warmup;
+            print::set_enabled(false);
+            let start = Instant::now();
+            let result = self.clean().and_then(|_| self.build());
+            let total = start.elapsed();
+            print::set_enabled(true);
+            if result.is_err() {
+                hintln!(
+                    "Hint",
+                    "The build output is hidden when benchmarking. Build without `bench` to see it"
+                );
+            }
+            result?;
+            let phases = bench::take_phases();
+            if is_warmup {
+                infoln!(
+                    "Warmup",
+                    "{}/{} took {:.2}s",
+                    i + 1,
+                    warmup,
+                    total.as_secs_f64()
+                );
+                continue;
+            }
+            infoln!(
+                "Run",
+                "{}/{} took {:.2}s",
+                i - warmup + 1,
+                runs,
+                total.as_secs_f64()
+            );
+            results.push(bench::Run { total, phases });
+        }
+        bench::print_summary(&results);
+        Ok(())
+    }
+
+    /// Invoke the exefs command
+    pub fn exefs(&self) -> Result<(), Error> {
+        let root_dir = &self.root_dir();
+        let config = self.load_config()?;
+        let exefs = match &config.exefs {
+            Some(exefs) => exefs.clone(),
+            None if self.options.package => Default::default(),
+            None => return Err(Error::MissingSection("exefs".to_string())),
+        };
+        let target_dir = self.profile_target_dir();
+        let build_dir = target_dir.join("make/build");
+        let exefs_dir = exefs::assemble_exefs(root_dir, build_dir, &target_dir, &config, &exefs)?;
+        if self.options.package {
+            let romfs_bin = target_dir.join("romfs.bin");
+            let romfs_bin = if config.romfs.is_some() {
+                if !romfs_bin.exists() {
+                    return Err(Error::MissingOutput(romfs_bin.display().to_string()));
+                }
+                Some(romfs_bin.as_path())
+            } else {
+                None
+            };
+            exefs::package_exefs(exefs_dir, romfs_bin, &config)?;
+        }
+        Ok(())
+    }
+
+    /// Invoke the deploy command
+    pub fn deploy(&self) -> Result<(), Error> {
+        let config = self.load_config()?;
+        let deploy = config
+            .deploy
+            .as_ref()
+            .ok_or_else(|| Error::MissingSection("deploy".to_string()))?;
+        if !self.options.emit.contains(&Emit::Nso) {
+            return Err(Error::InvalidOption(
+                "`deploy` requires `nso` in `--emit`".to_string(),
+            ));
+        }
+        self.build()?;
+
+        let target_dir = self.profile_target_dir();
+        let files = if config.exefs.is_some() {
+            let exefs_dir = target_dir.join("exefs");
+            let entries = std::fs::read_dir(&exefs_dir)
+                .map_err(|e| Error::AccessDirectory(exefs_dir.display().to_string(), e))?;
+            let mut files = Vec::new();
+            for entry in entries {
+                let entry = entry
+                    .map_err(|e| Error::AccessDirectory(exefs_dir.display().to_string(), e))?;
+                files.push((
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                ));
+            }
+            files.sort();
+            files
+        } else {
+            // the NSO, and the npdm if npdmtool was found when building
+            manifest::read_manifest(&target_dir)?
+                .artifacts
+                .into_iter()
+                .filter(|artifact| matches!(artifact.kind.as_str(), "nso" | "npdm"))
+                .map(|artifact| {
+                    let path = PathBuf::from(artifact.path);
+                    let name = path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    (name, path)
+                })
+                .collect()
+        };
+
+        let bytes = deploy::deploy_files(&files, deploy)?;
+        infoln!("Deployed", "{} file(s), {} bytes", files.len(), bytes);
+        Ok(())
+    }
+
+    /// Invoke the check command
+    pub fn check(&self, elf: Option<&str>) -> Result<(), Error> {
+        let objdump = devkitpro_tool(&devkitpro()?, "devkitA64/bin", "aarch64-none-elf-objdump")?;
+        let root_dir = &self.root_dir();
+        let config = self.load_config()?;
+        let check_config = config
+            .check
+            .as_ref()
+            .ok_or_else(|| Error::MissingSection("check".to_string()))?;
+        let check = check_config.get_profile(self.profile());
+
+        let elf_path = match elf {
+            Some(elf) => {
+                let elf_path = PathBuf::from(elf);
+                if !elf_path.exists() {
+                    return Err(Error::AccessFile(
+                        elf.to_string(),
+                        std::io::ErrorKind::NotFound.into(),
+                    ));
+                }
+                elf_path
+            }
+            None => {
+                let elf_path = self
+                    .profile_target_dir()
+                    .join("make/build")
+                    .join(format!("{}.elf", config.module.name));
+                if !elf_path.exists() {
+                    return Err(Error::MissingOutput(elf_path.display().to_string()));
+                }
+                elf_path
+            }
+        };
+
+        let sarif = self.options.sarif.as_ref().map(Path::new);
+        check::check_with_validators(root_dir, &elf_path, &check.external_validators, || {
+            check::check_symbols(
+                &elf_path,
+                &objdump,
+                &check,
+                || check::load_symbols(root_dir, &objdump, &check, self.options.io_jobs),
+                sarif.map(|sarif| (sarif, &[][..])),
+            )?;
+            if check.strict_exports.unwrap_or_default() {
+                // the version script is from the last build, even if another ELF is checked
+                let target_dir = self.profile_target_dir();
+                check::check_exports(
+                    &elf_path,
+                    &objdump,
+                    &target_dir.join("make/build/verfile"),
+                    &self.report_dir(&check).join("exports.txt"),
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Invoke the build-id command
+    pub fn build_id(&self, elf: Option<&str>) -> Result<(), Error> {
+        let elf_path = match elf {
+            Some(elf) => PathBuf::from(elf),
+            None => self.last_elf_path()?,
+        };
+        match elf::read_build_id(&elf_path)? {
+            Some(build_id) => {
+                println!("{}", build_id);
+                Ok(())
+            }
+            None => Err(Error::InvalidElf(
+                elf_path.display().to_string(),
+                "no build-id note found".to_string(),
+            )),
+        }
+    }
+
+    /// Invoke the size command
+    pub fn size(&self, elf: Option<&str>) -> Result<(), Error> {
+        let elf_path = match elf {
+            Some(elf) => PathBuf::from(elf),
+            None => self.last_elf_path()?,
+        };
+        size::report_size(&elf_path, &self.profile_target_dir().join("size.prev"))
+    }
+
+    /// Invoke the lint-config command
+    pub fn lint_config(&self) -> Result<(), Error> {
+        let config = self.load_config()?;
+        let make = config.make.get_profile(self.profile());
+        let build_dir = self.profile_target_dir().join("make/build");
+        if !build_dir.exists() {
+            return Err(Error::MissingOutput(build_dir.display().to_string()));
+        }
+        let unused = lint::find_unused_includes(&self.root_dir(), &build_dir, &make.includes)?;
+        for include in &unused {
+            hintln!(
+                "Warning",
+                "include directory `{}` is not used by any source",
+                include
+            );
+        }
+        if unused.is_empty() {
+            infoln!("Checked", "All include directories are used");
+        }
+        Ok(())
+    }
+
+    /// Get the command that compiles `source` (relative to Megaton.toml) with the
+    /// current config, without building
+    ///
+    /// The build directory must exist, since the command runs there.
+    pub fn dry_compile_command(&self, source: &str) -> Result<make::CompilerCommand, Error> {
+        let config = self.load_config()?;
+        let make_config = config.make.get_profile(self.profile());
+        let object =
+            make::source_object(&self.root_dir(), source, &make_config.extra_source_types)?;
+        let build_dir = self.profile_target_dir().join("make/build");
+        if !build_dir.exists() {
+            return Err(Error::MissingOutput(build_dir.display().to_string()));
+        }
+        let makefile = config.create_makefile(self, &build_dir)?;
+        let mut dkp_bin_path = Path::new(&devkitpro()?)
+            .join("devkitA64/bin")
+            .display()
+            .to_string();
+        if !dkp_bin_path.ends_with('/') {
+            dkp_bin_path.push('/');
+        }
+        make::dry_compile_command(&build_dir, &makefile, &object, &dkp_bin_path)
+    }
+
+    /// Run the preprocessor on `source` (relative to Megaton.toml) with the build flags
+    pub fn dump_preprocessed(&self, source: &str) -> Result<(), Error> {
+        let command = self.dry_compile_command(source)?;
+        let output = Path::new(&command.directory)
+            .join(&command.output)
+            .with_extension("i");
+        make::preprocess(&command, &output)?;
+        infoln!("Saved", "`{}`", output.display());
+        Ok(())
+    }
+
+    /// Get the directory for the report files of the check
+    fn report_dir(&self, check: &config::Check) -> PathBuf {
+        match &check.report_dir {
+            Some(report_dir) => self.root_dir().join(report_dir),
+            None => self.profile_target_dir(),
+        }
+    }
+
+    /// Get the path to the ELF from the last build, which must exist
+    fn last_elf_path(&self) -> Result<PathBuf, Error> {
+        let config = self.load_config()?;
+        let elf_path = self
+            .profile_target_dir()
+            .join("make/build")
+            .join(format!("{}.elf", config.module.name));
+        if !elf_path.exists() {
+            return Err(Error::MissingOutput(elf_path.display().to_string()));
+        }
+        Ok(elf_path)
+    }
+
+    /// Print the config with the profile applied, for `--print-config`
+    pub fn print_config(&self) -> Result<(), Error> {
+        let config = self.load_config()?;
+        let resolved = config.resolve_profile(self.profile());
+        match toml::to_string_pretty(&resolved) {
+            Ok(toml) => println!("{}", toml),
+            Err(e) => errorln!("Error", "Failed to serialize config: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Invoke the schema command
+    pub fn schema(&self) -> Result<(), Error> {
+        let schema = schemars::schema_for!(MegatonConfig);
+        match serde_json::to_string_pretty(&schema) {
+            Ok(json) => println!("{}", json),
+            Err(e) => errorln!("Error", "Failed to serialize schema: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Invoke the symbols command
+    pub fn symbols(&self, command: &SymbolsCommand) -> Result<(), Error> {
+        match command {
+            SymbolsCommand::Diff { a, b, json } => {
+                if *json {
+                    // only the JSON should be printed
+                    print::set_enabled(false);
+                }
+                let objdump =
+                    devkitpro_tool(&devkitpro()?, "devkitA64/bin", "aarch64-none-elf-objdump")?;
+                symbols::diff_symbols(objdump, a, b, *json)
+            }
+        }
+    }
+
     /// Invoke the clean command
     pub fn clean(&self) -> Result<(), Error> {
         let target_dir = self.target_dir();
@@ -242,14 +1176,209 @@ impl MegatonHammer {
         Ok(())
     }
 
+    /// Get the project root directory
+    ///
+    /// This is the directory of `--manifest-path` if specified (and not stdin), or `-C` otherwise.
+    pub fn root_dir(&self) -> PathBuf {
+        match (&self.manifest_path, &self.dir) {
+            (Some(manifest_path), _) if manifest_path != "-" => {
+                match Path::new(manifest_path).parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                    _ => PathBuf::from("."),
+                }
+            }
+            (_, Some(dir)) => PathBuf::from(dir),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    /// Load the config with the `--config-override` values
+    fn load_config(&self) -> Result<MegatonConfig, Error> {
+        let mut config = MegatonConfig::from_path_with_overrides(
+            self.manifest_path(),
+            &self.options.config_override,
+        )?;
+        config.module.resolve_title_id(&self.root_dir())?;
+        Ok(config)
+    }
+
+    /// Get the path to the config file, which is `-` if it's read from stdin
+    pub fn manifest_path(&self) -> PathBuf {
+        match &self.manifest_path {
+            Some(manifest_path) => PathBuf::from(manifest_path),
+            None => self.root_dir().join("Megaton.toml"),
+        }
+    }
+
     pub fn target_dir(&self) -> PathBuf {
-        Path::new(&self.dir).join("target/megaton")
+        self.root_dir().join("target/megaton")
     }
+
+    /// Get the build flavor, either `debug` or `release`
+    pub fn flavor(&self) -> &'static str {
+        if self.options.release {
+            "release"
+        } else {
+            "debug"
+        }
+    }
+
+    /// Get the build profile, which is `none` if not specified
+    pub fn profile(&self) -> &str {
+        self.options.profile.as_deref().unwrap_or("none")
+    }
+
+    /// Get the target directory for the current flavor and profile
+    pub fn profile_target_dir(&self) -> PathBuf {
+        self.target_dir().join(self.flavor()).join(self.profile())
+    }
+}
+
+/// Remove the ELF after it's checked, so it's linked and checked again in the next build
+///
+/// If the check `failed` and `rename_on_fail`, the ELF is kept as `.bad.elf` to inspect it.
+/// If the check passed and `keep_passed`, the ELF is not removed.
+fn remove_checked_elf(
+    elf_path: &Path,
+    failed: bool,
+    rename_on_fail: bool,
+    keep_passed: bool,
+) -> Result<(), Error> {
+    if failed && rename_on_fail {
+        let bad_elf_path = elf_path.with_extension("bad.elf");
+        std::fs::rename(elf_path, &bad_elf_path)
+            .map_err(|e| Error::AccessFile(elf_path.display().to_string(), e))?;
+        hintln!(
+            "Hint",
+            "The failed ELF is kept at `{}`",
+            bad_elf_path.display()
+        );
+    } else if failed || !keep_passed {
+        std::fs::remove_file(elf_path)
+            .map_err(|e| Error::AccessFile(elf_path.display().to_string(), e))?;
+    }
+    Ok(())
+}
+
+/// Get the `DEVKITPRO` environment variable
+fn devkitpro() -> Result<String, Error> {
+    let env_dev_kit_pro = std::env::var("DEVKITPRO").unwrap_or_default();
+    if env_dev_kit_pro.is_empty() {
+        return Err(Error::MissingEnv(
+            "DEVKITPRO".to_string(),
+            "Please ensure devkitPro is installed in the system.".to_string(),
+        ));
+    }
+    Ok(env_dev_kit_pro)
+}
+
+/// Get the path to a tool in the devkitPro installation, and check that it exists
+fn devkitpro_tool(env_dev_kit_pro: &str, dir: &str, name: &str) -> Result<PathBuf, Error> {
+    let tool = Path::new(env_dev_kit_pro).join(dir).join(name);
+    if which::which(&tool).is_err() {
+        return Err(Error::MissingTool(
+            name.to_string(),
+            "Please ensure devkitPro is installed in the system.".to_string(),
+        ));
+    }
+    Ok(tool)
 }
 
-fn get_modified_time(path: &Path) -> Option<SystemTime> {
-    if !path.exists() {
-        return None;
+/// Print all the matches of a tool in `PATH` and the one that is used.
+///
+/// This is only for diagnosing `PATH` ordering problems. It doesn't affect which tool is used.
+fn print_tool_resolution(name: &str, chosen: &Path) {
+    infoln!("Tool", "{} -> {}", name, chosen.display());
+    let chosen = chosen.canonicalize().unwrap_or(chosen.to_path_buf());
+    let mut others = Vec::new();
+    if let Ok(candidates) = which::which_all(name) {
+        for candidate in candidates {
+            // the same file can be found through symlinked directories in PATH
+            let resolved = candidate.canonicalize().unwrap_or(candidate.clone());
+            if resolved != chosen && !others.contains(&resolved) {
+                infoln!("Tool", "  also found: {}", candidate.display());
+                others.push(resolved);
+            }
+        }
+    }
+    if !others.is_empty() {
+        hintln!(
+            "Hint",
+            "Multiple `{}` found. Make sure the one used is the one you expect.",
+            name
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the profile from the `args` and `default_profile`, with `MEGATON_PROFILE` set to `env`
+    fn resolve_profile(args: &[&str], env: Option<&str>, default_profile: Option<&str>) -> String {
+        match env {
+            Some(env) => std::env::set_var("MEGATON_PROFILE", env),
+            None => std::env::remove_var("MEGATON_PROFILE"),
+        }
+        let cli =
+            MegatonHammer::try_parse_from(std::iter::once("megaton").chain(args.iter().copied()))
+                .unwrap();
+        cli.with_default_profile(default_profile.map(str::to_string))
+            .profile()
+            .to_string()
+    }
+
+    #[test]
+    fn profile_precedence() {
+        // MEGATON_PROFILE is only read in this test, so setting it doesn't affect other tests
+        assert_eq!(resolve_profile(&[], None, None), "none");
+        assert_eq!(resolve_profile(&[], None, Some("default")), "default");
+        assert_eq!(resolve_profile(&[], Some("env"), None), "env");
+        assert_eq!(resolve_profile(&[], Some("env"), Some("default")), "env");
+        assert_eq!(resolve_profile(&["-p", "cli"], None, None), "cli");
+        assert_eq!(
+            resolve_profile(&["-p", "cli"], None, Some("default")),
+            "cli"
+        );
+        assert_eq!(
+            resolve_profile(&["--profile", "cli"], Some("env"), None),
+            "cli"
+        );
+        assert_eq!(
+            resolve_profile(&["--profile", "cli"], Some("env"), Some("default")),
+            "cli"
+        );
+        std::env::remove_var("MEGATON_PROFILE");
+    }
+
+    #[test]
+    fn failed_elf_is_checked_again() {
+        let dir = fs::test_dir("checked-elf");
+        let elf = dir.join("test.elf");
+        let bad_elf = dir.join("test.bad.elf");
+        std::fs::write(&elf, "first").unwrap();
+        remove_checked_elf(&elf, true, true, false).unwrap();
+        // without the ELF, the next build links and checks it again
+        assert_eq!(fs::get_modified_time(&elf), None);
+        assert_eq!(std::fs::read_to_string(&bad_elf).unwrap(), "first");
+        // the ELF of the next failure replaces the old one
+        std::fs::write(&elf, "second").unwrap();
+        remove_checked_elf(&elf, true, true, true).unwrap();
+        assert!(!elf.exists());
+        assert_eq!(std::fs::read_to_string(&bad_elf).unwrap(), "second");
+        std::fs::remove_file(&bad_elf).unwrap();
+        // without rename-on-fail, the failed ELF is removed even if it's the output
+        std::fs::write(&elf, "").unwrap();
+        remove_checked_elf(&elf, true, false, true).unwrap();
+        assert!(!elf.exists());
+        assert!(!bad_elf.exists());
+        // a passed ELF is kept only if it's the output
+        std::fs::write(&elf, "").unwrap();
+        remove_checked_elf(&elf, false, true, true).unwrap();
+        assert!(elf.exists());
+        remove_checked_elf(&elf, false, true, false).unwrap();
+        assert!(!elf.exists());
+        assert!(!bad_elf.exists());
+        let _ = std::fs::remove_dir_all(&dir);
     }
-    path.metadata().and_then(|m| m.modified()).ok()
 }
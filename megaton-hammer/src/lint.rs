@@ -0,0 +1,55 @@
+//! The `lint-config` command for finding stale config entries
+//!
+//! The dependency files (`*.d`) from the last build list every header that was
+//! included, so an include directory that none of them point into is unused.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::make;
+
+/// Find the entries in `includes` (relative to `root_dir`) that no source in `build_dir` depends on
+pub fn find_unused_includes(
+    root_dir: &Path,
+    build_dir: &Path,
+    includes: &[String],
+) -> Result<Vec<String>, Error> {
+    let dependencies = read_dependencies(build_dir)?;
+    let unused = includes
+        .iter()
+        .filter(|include| {
+            // an include that doesn't exist can't be used
+            let Ok(dir) = root_dir.join(include).canonicalize() else {
+                return true;
+            };
+            !dependencies.iter().any(|path| path.starts_with(&dir))
+        })
+        .cloned()
+        .collect();
+    Ok(unused)
+}
+
+/// Read all the prerequisites from the dependency files in `build_dir`
+fn read_dependencies(build_dir: &Path) -> Result<BTreeSet<PathBuf>, Error> {
+    let entries = std::fs::read_dir(build_dir)
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    let mut dependencies = BTreeSet::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?
+            .path();
+        if path.extension().and_then(|x| x.to_str()) != Some("d") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        for dependency in make::parse_dependency_file(&content) {
+            // relative paths in the dependency files are relative to the build directory
+            if let Ok(dependency) = build_dir.join(dependency).canonicalize() {
+                dependencies.insert(dependency);
+            }
+        }
+    }
+    Ok(dependencies)
+}
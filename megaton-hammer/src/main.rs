@@ -5,6 +5,6 @@ fn main() {
     let cli = MegatonHammer::parse();
     if let Err(e) = cli.invoke() {
         e.print();
-        std::process::exit(1);
+        std::process::exit(e.exit_code());
     }
 }
@@ -2,17 +2,26 @@
 //!
 //! Megaton puts the artifacts in the `./target/megaton/<flavor>/<profile>/make` directory:
 //! - `build.mk`: The Makefile
+//! - `sources.mk`: The sources to compile, if `respect-gitignore` is enabled
+//! - `build/source.hashes`: The content hashes of the objects' inputs, if `hash-based` is enabled
 //! - `build`: The build output directory
 
-use std::collections::BTreeMap;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::SystemTime;
 
+use crate::config::{Compiler, ExtraSourceType, Make, SourceKind};
+use crate::diagnostic::{self, Diagnostic};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::error::Error;
-use crate::{errorln, infoln, MegatonConfig, MegatonHammer};
+use crate::fs::{self, FileSystem};
+use crate::process;
+use crate::{errorln, hintln, infoln, print, verboseln, MegatonConfig, MegatonHammer};
 
 macro_rules! format_makefile_template {
     ($($args:tt)*) => {
@@ -29,10 +38,12 @@ MEGATON_ROOT := {MEGATON_ROOT}
 TARGET := $(MEGATON_MODULE_NAME)
 VERFILE := verfile
 
+{COMPILER_SECTION}
+
 DEFAULT_ARCH_FLAGS := \
     -march=armv8-a+crc+crypto \
     -mtune=cortex-a57 \
-    -mtp=soft \
+    $(GCC_ARCH_FLAGS) \
     -fPIC \
     -fvisibility=hidden \
 
@@ -52,8 +63,7 @@ DEFAULT_CXXFLAGS := \
     -fno-exceptions \
     -fno-asynchronous-unwind-tables \
     -fno-unwind-tables \
-    -fpermissive \
-    -std=gnu++20 \
+    $(GCC_CXXFLAGS) \
 
 DEFAULT_ASFLAGS := -g
 DEFAULT_LDFLAGS := \
@@ -75,36 +85,55 @@ DEFAULT_LIBS :=
 {EXTRA_SECTION}
 
 SOURCES          := $(SOURCES) {SOURCES}
-ALL_SOURCE_DIRS  := $(ALL_SOURCE_DIRS) $(foreach dir,$(SOURCES),$(shell find $(dir) -type d))
-VPATH            := $(VPATH) $(ALL_SOURCE_DIRS)
+# the source files listed in `sources`, only their directories are in VPATH
+SOURCE_ENTRIES   := {SOURCE_ENTRIES}
+ALL_SOURCE_DIRS  := $(sort $(ALL_SOURCE_DIRS) $(foreach dir,$(SOURCES),$(shell find $(dir) -type d)))
+VPATH            := $(VPATH) $(ALL_SOURCE_DIRS) $(sort $(dir $(SOURCE_ENTRIES)))
 
 INCLUDES         := $(INCLUDES) {INCLUDES}
+SYSTEM_INCLUDES  := $(SYSTEM_INCLUDES) {SYSTEM_INCLUDES}
+AS_INCLUDES      := $(AS_INCLUDES) {AS_INCLUDES}
 LIBDIRS          := $(LIBDIRS) $(PORTLIBS) $(LIBNX)
-INCLUDE_FLAGS    := $(foreach dir,$(INCLUDES),-I$(dir)) $(foreach dir,$(LIBDIRS),-I$(dir)/include)
+# library headers are system headers, so their warnings don't fail the build with -Werror
+INCLUDE_FLAGS    := $(foreach dir,$(INCLUDES),-I$(dir)) $(foreach dir,$(LIBDIRS),-isystem $(dir)/include) \
+                    $(foreach dir,$(SYSTEM_INCLUDES),-isystem $(dir))
 
 DEFINES          := $(DEFINES) {DEFINES}
 
 ARCH_FLAGS       := $(ARCH_FLAGS) {ARCH_FLAGS}
-CFLAGS           := $(CFLAGS) $(ARCH_FLAGS) $(DEFINES) $(INCLUDE_FLAGS) {CFLAGS}
-CXXFLAGS         := $(CFLAGS) $(CXXFLAGS) {CXXFLAGS}
-ASFLAGS          := $(ASFLAGS) $(ARCH_FLAGS) {ASFLAGS}
+CFLAGS           := $(CFLAGS) $(ARCH_FLAGS) $(DEFINES) $(INCLUDE_FLAGS) {CFLAGS} {SPLIT_DEBUG_CFLAGS} \
+                    $(DIAGNOSTICS_FLAGS)
+CXXFLAGS         := $(CFLAGS) $(CXXFLAGS) {CXXFLAGS} {CPP_STANDARD}
+CFLAGS           := $(CFLAGS) {C_STANDARD} {RAW_CC}
+CXXFLAGS         := $(CXXFLAGS) {RAW_CC}
+ASFLAGS          := $(ASFLAGS) $(ARCH_FLAGS) $(foreach dir,$(AS_INCLUDES),-I$(dir)) {ASFLAGS}
 
 LD_SCRIPTS       := {LD_SCRIPTS}
 LD_SCRIPTS_FLAGS := $(foreach ld,$(LD_SCRIPTS),-Wl,-T,$(ld))
 LD               := $(CXX)
-LDFLAGS          := $(LDFLAGS) $(ARCH_FLAGS) $(LD_SCRIPTS_FLAGS) {LDFLAGS}
-LIBS             := $(LIBS) {LIBS}
+LDFLAGS          := $(LDFLAGS) $(ARCH_FLAGS) $(LD_SCRIPTS_FLAGS) {LDFLAGS} {SPLIT_DEBUG_LDFLAGS}
+LDFLAGS          := $(LDFLAGS) {RAW_LD}
+# not in OFILES, so they are still linked when OFILES is overridden
+EXTRA_OBJECTS    := {EXTRA_OBJECTS}
+LIBS             := $(EXTRA_OBJECTS) $(LIBS) {LIBS} {LIBRARIES}
+LIBRARY_FILES    := {LIBRARY_FILES}
 LIBPATHS         := $(LIBPATHS) $(foreach dir,$(LIBDIRS),-L$(dir)/lib) 
 
 DEPSDIR          ?= .
-CFILES           := $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.c)))
-CPPFILES         := $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.cpp)))
-SFILES           := $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.s)))
-OFILES           := $(CPPFILES:.cpp=.o) $(CFILES:.c=.o) $(SFILES:.s=.o)
+# sorted so the link order doesn't depend on the file system
+CFILES           := $(sort $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.c))) \
+                    $(notdir $(filter %.c,$(SOURCE_ENTRIES))))
+CPPFILES         := $(sort $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.cpp))) \
+                    $(notdir $(filter %.cpp,$(SOURCE_ENTRIES))))
+SFILES           := $(sort $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.s))) \
+                    $(notdir $(filter %.s,$(SOURCE_ENTRIES))))
+{SOURCE_FILES}
+{EXTRA_SOURCE_FILES}
+OFILES           := $(CPPFILES:.cpp=.o) $(CFILES:.c=.o) $(SFILES:.s=.o) $(EXTRA_OFILES)
 DFILES           := $(OFILES:.o=.d)
 
 $(TARGET).nso: $(TARGET).elf
-$(TARGET).elf: $(OFILES) $(LD_SCRIPTS) $(VERFILE)
+$(TARGET).elf: $(OFILES) $(LD_SCRIPTS) $(VERFILE) link.stamp $(LIBRARY_FILES) $(EXTRA_OBJECTS)
 $(VERFILE):
 	@echo $(VERFILE)
 	@echo "{{" > $(VERFILE)
@@ -114,6 +143,7 @@ $(VERFILE):
 	@echo "}};" >> $(VERFILE)
 
 -include $(DFILES)
+{EXTRA_SOURCE_RULES}
 
 "###,
         $($args)*
@@ -133,51 +163,185 @@ macro_rules! default_or_empty {
 
 impl MegatonConfig {
     /// Create the Makefile content from the config
-    pub fn create_makefile(&self, cli: &MegatonHammer) -> Result<String, Error> {
-        let mut root = Path::new(&cli.dir)
-            .canonicalize()
-            .map_err(|e| Error::AccessDirectory(cli.dir.clone(), e))?
-            .display()
-            .to_string();
+    ///
+    /// `build_dir` is the directory make will be invoked in. It is used to
+    /// compute the root path when `relative-paths` is enabled.
+    pub fn create_makefile(&self, cli: &MegatonHammer, build_dir: &Path) -> Result<String, Error> {
+        let make = self.make.get_profile(cli.profile());
+
+        let mut root = if make.relative_paths.unwrap_or_default() {
+            relative_root(&cli.root_dir(), build_dir)
+        } else {
+            let root_dir = cli.root_dir();
+            root_dir
+                .canonicalize()
+                .map_err(|e| Error::AccessDirectory(root_dir.display().to_string(), e))?
+                .display()
+                .to_string()
+        };
         if !root.ends_with('/') {
             root.push('/');
         }
 
-        let make = self.make.get_profile(&cli.options.profile);
-
         let entry = make.entry.as_ref().ok_or(Error::NoEntryPoint)?;
 
-        let extra_section = make
+        let mut extra_section = make
             .extra
             .iter()
             .map(|s| format!("{} := {}", s.key, s.val))
-            .collect::<Vec<_>>()
-            .join("\n");
+            .collect::<Vec<_>>();
+        // flag file contents are embedded in the Makefile, so changing them
+        // causes the Makefile to be regenerated and everything to be rebuilt
+        for flag_file in &make.flag_files {
+            let flags = read_flag_file(cli.root_dir().join(&flag_file.val))?;
+            extra_section.push(format!(
+                "{} := $({}) {}",
+                flag_file.key,
+                flag_file.key,
+                flags.join(" ")
+            ));
+        }
+        let extra_section = extra_section.join("\n");
 
-        let sources = make
-            .sources
-            .iter()
-            .map(|s| format!("$(MEGATON_ROOT){s}"))
-            .collect::<Vec<_>>()
-            .join(" ");
-        let includes = make
-            .includes
-            .iter()
-            .map(|s| format!("$(MEGATON_ROOT){s}"))
-            .collect::<Vec<_>>()
-            .join(" ");
-        let ld_scripts = make
-            .ld_scripts
-            .iter()
+        // files are compiled directly, instead of searching them like directories
+        let mut sources = Vec::new();
+        let mut source_entries = Vec::new();
+        for s in unique(&make.sources) {
+            let path = cli.root_dir().join(s);
+            if !path.is_file() {
+                sources.push(format!("$(MEGATON_ROOT){s}"));
+                continue;
+            }
+            if !is_source(&path, &make.extra_source_types) {
+                return Err(Error::ParseConfig(format!(
+                    "`{}` in `sources` is not a directory or a source file",
+                    s
+                )));
+            }
+            source_entries.push(format!("$(MEGATON_ROOT){s}"));
+        }
+        let sources = sources.join(" ");
+        let source_entries = source_entries.join(" ");
+        let include_dirs = |dirs: &[String]| {
+            unique(dirs)
+                .into_iter()
+                .map(|s| {
+                    // the compiler ignores include directories that don't exist, which is
+                    // fine for generated directories, but could also be a typo
+                    let path = cli.root_dir().join(s);
+                    if !path.is_dir() {
+                        hintln!("Warning", "include directory `{}` does not exist", s);
+                    }
+                    if Path::new(s).is_absolute() {
+                        s.to_string()
+                    } else {
+                        format!("$(MEGATON_ROOT){s}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let includes = include_dirs(&make.includes);
+        let system_includes = include_dirs(&make.system_includes);
+        let as_includes = include_dirs(&make.as_includes);
+        // generated scripts are in the `ld` directory next to the build directory
+        let ld_scripts = unique(&make.ld_scripts)
+            .into_iter()
             .map(|s| format!("$(MEGATON_ROOT){s}"))
+            .chain(
+                unique(&make.ld_script_templates)
+                    .into_iter()
+                    .map(|s| format!("../ld/{}", ld_script_template_name(s))),
+            )
             .collect::<Vec<_>>()
             .join(" ");
-        let defines = make
-            .defines
-            .iter()
+        let mut defines = unique(&make.defines)
+            .into_iter()
             .map(|s| format!("-D{s}"))
-            .collect::<Vec<_>>()
-            .join(" ");
+            .collect::<Vec<_>>();
+        // the values are embedded in the Makefile, so a changed value rebuilds everything
+        for name in unique(&make.defines_from_env) {
+            match std::env::var(name) {
+                Ok(value) => defines.push(env_define(name, &value)),
+                Err(_) if make.allow_missing_env.unwrap_or_default() => {
+                    hintln!("Warning", "environment variable `{}` is not set", name);
+                }
+                Err(_) => {
+                    return Err(Error::MissingEnv(
+                        name.to_string(),
+                        "It is listed in `defines-from-env`.".to_string(),
+                    ))
+                }
+            }
+        }
+        let defines = defines.join(" ");
+        // libraries given as paths are linked directly, and relinked when they change
+        let mut libraries = Vec::new();
+        let mut library_files = Vec::new();
+        for library in unique(&make.libraries) {
+            if library.starts_with(':') {
+                libraries.push(format!("-l{library}"));
+            } else if library.contains('/') {
+                let path = if Path::new(library).is_absolute() {
+                    library.to_string()
+                } else {
+                    format!("$(MEGATON_ROOT){library}")
+                };
+                libraries.push(path.clone());
+                library_files.push(path);
+            } else {
+                libraries.push(format!("-l{library}"));
+            }
+        }
+        // precompiled objects are linked directly, and relinked when they change
+        let mut extra_objects = Vec::new();
+        for object in unique(&make.extra_objects) {
+            let path = cli.root_dir().join(object);
+            if !path.is_file() {
+                return Err(Error::AccessFile(
+                    path.display().to_string(),
+                    std::io::ErrorKind::NotFound.into(),
+                ));
+            }
+            if Path::new(object).is_absolute() {
+                extra_objects.push(object.to_string());
+            } else {
+                extra_objects.push(format!("$(MEGATON_ROOT){object}"));
+            }
+        }
+        let raw_cc = make.raw_cc.join(" ");
+        let raw_ld = make.raw_ld.join(" ");
+        let cpp_standard = match &make.cpp_standard {
+            Some(standard) => format!("-std={}", validate_standard(standard, CPP_STANDARDS)?),
+            None => default_or_empty!(make, "-std=gnu++20").to_string(),
+        };
+        let c_standard = match &make.c_standard {
+            Some(standard) => format!("-std={}", validate_standard(standard, C_STANDARDS)?),
+            None => String::new(),
+        };
+        // the list is in a separate file so adding a source doesn't change the Makefile
+        let respect_gitignore = make.respect_gitignore.unwrap_or_default();
+        let source_files = if respect_gitignore {
+            "include ../sources.mk"
+        } else {
+            ""
+        };
+        // the default flags that clang doesn't support are only added for gcc
+        let compiler_section = match make.compiler.unwrap_or_default() {
+            Compiler::Gcc => "GCC_ARCH_FLAGS := -mtp=soft\nGCC_CXXFLAGS := -fpermissive",
+            Compiler::Clang => {
+                "CC := clang --target=aarch64-none-elf\nCXX := clang++ --target=aarch64-none-elf\nGCC_ARCH_FLAGS :=\nGCC_CXXFLAGS :="
+            }
+        };
+        let (split_debug_cflags, split_debug_ldflags) = if make.split_debug.unwrap_or_default() {
+            // the dwp from binutils can't package DWARF 5 split units, which is the
+            // default since GCC 11
+            ("-gsplit-dwarf -gdwarf-4", "-Wl,--gdb-index")
+        } else {
+            ("", "")
+        };
+        let (extra_source_files, extra_source_rules) =
+            extra_source_sections(&make.extra_source_types, !respect_gitignore)?;
 
         let makefile = format_makefile_template!(
             MEGATON_MODULE_NAME = self.module.name,
@@ -186,7 +350,10 @@ impl MegatonConfig {
             MEGATON_ROOT = root,
             EXTRA_SECTION = extra_section,
             SOURCES = sources,
+            SOURCE_ENTRIES = source_entries,
             INCLUDES = includes,
+            SYSTEM_INCLUDES = system_includes,
+            AS_INCLUDES = as_includes,
             DEFINES = defines,
             ARCH_FLAGS = default_or_empty!(make, "$(DEFAULT_ARCH_FLAGS)"),
             CFLAGS = default_or_empty!(make, "$(DEFAULT_CFLAGS)"),
@@ -194,13 +361,300 @@ impl MegatonConfig {
             ASFLAGS = default_or_empty!(make, "$(DEFAULT_ASFLAGS)"),
             LD_SCRIPTS = ld_scripts,
             LDFLAGS = default_or_empty!(make, "$(DEFAULT_LDFLAGS)"),
+            RAW_CC = raw_cc,
+            RAW_LD = raw_ld,
             LIBS = default_or_empty!(make, "$(DEFAULT_LIBS)"),
+            LIBRARIES = libraries.join(" "),
+            LIBRARY_FILES = library_files.join(" "),
+            EXTRA_OBJECTS = extra_objects.join(" "),
+            SOURCE_FILES = source_files,
+            EXTRA_SOURCE_FILES = extra_source_files,
+            EXTRA_SOURCE_RULES = extra_source_rules,
+            CPP_STANDARD = cpp_standard,
+            COMPILER_SECTION = compiler_section,
+            SPLIT_DEBUG_CFLAGS = split_debug_cflags,
+            SPLIT_DEBUG_LDFLAGS = split_debug_ldflags,
+            C_STANDARD = c_standard,
         );
 
         Ok(makefile)
     }
 }
 
+/// The built-in source extensions
+const SOURCE_TYPES: &[(&str, SourceKind)] = &[
+    ("c", SourceKind::C),
+    ("cpp", SourceKind::Cpp),
+    ("s", SourceKind::As),
+];
+
+/// Check if `path` has one of the built-in or `extra` source extensions
+fn is_source(path: &Path, extra: &[ExtraSourceType]) -> bool {
+    let Some(ext) = path.extension().and_then(|x| x.to_str()) else {
+        return false;
+    };
+    SOURCE_TYPES.iter().any(|(e, _)| *e == ext) || extra.iter().any(|t| t.ext == ext)
+}
+
+/// Create the Makefile sections for `extra-source-types`
+///
+/// Returns the lines adding the objects to `EXTRA_OFILES`, which are only
+/// needed if `list_files` (otherwise `sources.mk` has the objects), and the
+/// pattern rules to compile them. The language is passed with `-x`, since the
+/// compiler can't tell it from an unknown extension.
+fn extra_source_sections(
+    types: &[ExtraSourceType],
+    list_files: bool,
+) -> Result<(String, String), Error> {
+    let mut seen = BTreeSet::new();
+    let mut files = Vec::new();
+    let mut rules = Vec::new();
+    for source_type in types {
+        let ext = source_type.ext.as_str();
+        if ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::ParseConfig(format!(
+                "invalid extension `{}` in `extra-source-types`. Extensions should not include the `.`",
+                ext
+            )));
+        }
+        if SOURCE_TYPES.iter().any(|(e, _)| *e == ext) {
+            return Err(Error::ParseConfig(format!(
+                "`.{}` is a built-in source type and cannot be remapped in `extra-source-types`",
+                ext
+            )));
+        }
+        if !seen.insert(ext) {
+            return Err(Error::ParseConfig(format!(
+                "`.{}` is mapped more than once in `extra-source-types`",
+                ext
+            )));
+        }
+        if list_files {
+            files.push(format!(
+                "EXTRA_OFILES     := $(EXTRA_OFILES) $(patsubst %.{ext},%.o,$(sort $(foreach dir,$(ALL_SOURCE_DIRS),$(notdir $(wildcard $(dir)/*.{ext}))) $(notdir $(filter %.{ext},$(SOURCE_ENTRIES)))))"
+            ));
+        }
+        let command = match source_type.kind {
+            SourceKind::C => "$(CC) -MMD -MP -MF $(DEPSDIR)/$*.d -x c $(CPPFLAGS) $(CFLAGS)",
+            SourceKind::Cpp => "$(CXX) -MMD -MP -MF $(DEPSDIR)/$*.d -x c++ $(CPPFLAGS) $(CXXFLAGS)",
+            SourceKind::As => {
+                "$(CC) -MMD -MP -MF $(DEPSDIR)/$*.d -x assembler-with-cpp $(CPPFLAGS) $(ASFLAGS)"
+            }
+        };
+        rules.push(format!(
+            "%.o: %.{ext}\n\t@echo $(notdir $<)\n\t$(SILENTCMD){command} -c $< -o $@ $(ERROR_FILTER)\n"
+        ));
+    }
+    Ok((files.join("\n"), rules.join("\n")))
+}
+
+/// Remove duplicates from `values`, keeping the first occurrence
+///
+/// Paths that only differ by a trailing `/` are duplicates. This is for lists where each
+/// entry is a single value, like includes and defines. The raw flags are not deduplicated,
+/// since a repeated flag can be part of a pair (like `-Xlinker <arg>`).
+fn unique(values: &[String]) -> Vec<&str> {
+    let mut seen = BTreeSet::new();
+    values
+        .iter()
+        .map(|value| value.as_str())
+        .filter(|value| seen.insert(value.trim_end_matches('/')))
+        .collect()
+}
+
+/// Create the flag that defines `name` as a string literal of `value`
+///
+/// The value is escaped for the C string literal, then the flag is single-quoted for the
+/// shell running the compiler, and `$` and `#` are escaped for make.
+fn env_define(name: &str, value: &str) -> String {
+    let literal = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    let flag = format!("-D{name}=\"{literal}\"");
+    let flag = format!("'{}'", flag.replace('\'', "'\\''"));
+    flag.replace('$', "$$").replace('#', "\\#")
+}
+
+/// Values accepted by `cpp-standard`
+const CPP_STANDARDS: &[&str] = &[
+    "c++98", "c++03", "c++11", "c++14", "c++17", "c++20", "c++23", "c++26", "gnu++98", "gnu++03",
+    "gnu++11", "gnu++14", "gnu++17", "gnu++20", "gnu++23", "gnu++26",
+];
+
+/// Values accepted by `c-standard`
+const C_STANDARDS: &[&str] = &[
+    "c89", "c90", "c99", "c11", "c17", "c18", "c23", "gnu89", "gnu90", "gnu99", "gnu11", "gnu17",
+    "gnu18", "gnu23",
+];
+
+/// Check that `standard` is one of `known`, so a typo doesn't become a confusing compiler error
+fn validate_standard<'a>(standard: &'a str, known: &[&str]) -> Result<&'a str, Error> {
+    if known.contains(&standard) {
+        Ok(standard)
+    } else {
+        Err(Error::ParseConfig(format!(
+            "unknown language standard `{}`. Expected one of: {}",
+            standard,
+            known.join(", ")
+        )))
+    }
+}
+
+/// Check if the Makefile at `makefile_path` needs to be replaced with `makefile`
+pub fn is_makefile_changed(fs: &impl FileSystem, makefile_path: &Path, makefile: &str) -> bool {
+    if !fs.exists(makefile_path) {
+        return true;
+    }
+    match fs.read_file(makefile_path) {
+        Ok(old_makefile) => old_makefile != makefile,
+        Err(_) => true,
+    }
+}
+
+/// Write `link.stamp` in `build_dir`, which the ELF depends on
+///
+/// The stamp has the linker scripts in the listed order with the hashes of their content.
+/// It is only rewritten when it changes, so the ELF is relinked exactly when the scripts
+/// are added, removed, reordered or edited, regardless of their modified times.
+pub fn write_link_stamp(build_dir: &Path, ld_scripts: &[PathBuf]) -> Result<(), Error> {
+    let mut stamp = String::new();
+    for path in ld_scripts {
+        let content =
+            std::fs::read(path).map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        let hash = Sha256::digest(content);
+        let hash = hash
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        stamp.push_str(&format!("{hash} {}\n", path.display()));
+    }
+    let stamp_path = build_dir.join("link.stamp");
+    if std::fs::read_to_string(&stamp_path).ok().as_deref() == Some(stamp.as_str()) {
+        return Ok(());
+    }
+    verboseln!(1, "Saved", "link.stamp");
+    fs::write_file(stamp_path, stamp)
+}
+
+/// Generate the linker scripts from `ld-script-templates` into `<make_dir>/ld`
+///
+/// `${MODULE_NAME}`, `${OUTPUT_NAME}`, `${ENTRY}`, `${TITLE_ID}` (16 hex digits, no `0x`)
+/// and `${TARGET_DIR}` (absolute) in the templates are substituted. The scripts are only
+/// rewritten when they change. Returns the paths to the generated scripts.
+pub fn generate_ld_scripts(
+    root_dir: &Path,
+    make_dir: &Path,
+    target_dir: &Path,
+    config: &MegatonConfig,
+    make: &Make,
+) -> Result<Vec<PathBuf>, Error> {
+    let target_dir = target_dir
+        .canonicalize()
+        .map_err(|e| Error::AccessDirectory(target_dir.display().to_string(), e))?;
+    let vars = [
+        ("MODULE_NAME", config.module.name.clone()),
+        ("OUTPUT_NAME", config.module.output_name().to_string()),
+        ("ENTRY", make.entry.clone().unwrap_or_default()),
+        ("TITLE_ID", config.module.title_id_hex()),
+        ("TARGET_DIR", target_dir.display().to_string()),
+    ];
+    let mut scripts = Vec::new();
+    for template in unique(&make.ld_script_templates) {
+        let path = root_dir.join(template);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        let content = substitute_vars(template, &content, &vars)?;
+        let name = ld_script_template_name(template);
+        let script = make_dir.join("ld").join(name);
+        if std::fs::read_to_string(&script).ok().as_deref() != Some(content.as_str()) {
+            fs::write_file(&script, content)?;
+            verboseln!(1, "Generated", "`{}`", script.display());
+        }
+        scripts.push(script);
+    }
+    Ok(scripts)
+}
+
+/// Get the file name of the linker script generated from `template`
+fn ld_script_template_name(template: &str) -> String {
+    Path::new(template)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| template.to_string())
+}
+
+/// Replace `${NAME}` in `content` with the values in `vars`
+///
+/// `id` identifies the content in the error for an unknown or unclosed variable.
+fn substitute_vars(id: &str, content: &str, vars: &[(&str, String)]) -> Result<String, Error> {
+    let mut output = String::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| Error::ParseConfig(format!("unclosed `${{` in `{}`", id)))?;
+        let name = &after[..end];
+        let value = vars
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| {
+                Error::ParseConfig(format!("unknown variable `${{{}}}` in `{}`", name, id))
+            })?;
+        output.push_str(value);
+        rest = &after[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Read a file with one flag per line, skipping blank lines and `#` comments
+fn read_flag_file(path: PathBuf) -> Result<Vec<String>, Error> {
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    let flags = content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect();
+    Ok(flags)
+}
+
+/// Get the path to `root_dir` relative to `build_dir`, which must be inside `root_dir`
+fn relative_root(root_dir: &Path, build_dir: &Path) -> String {
+    let depth = build_dir
+        .strip_prefix(root_dir)
+        .map(|p| {
+            p.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count()
+        })
+        .unwrap_or_default();
+    if depth == 0 {
+        return "./".to_string();
+    }
+    "../".repeat(depth)
+}
+
+/// Lexically resolve `.` and `..` in a path without touching the file system
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 /// Compiler command for IDE integration. See
 /// <https://clang.llvm.org/docs/JSONCompilationDatabase.html>
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -216,6 +670,11 @@ pub struct CompilerCommand {
 }
 
 impl CompilerCommand {
+    /// Check if a line printed by make is a compiler command
+    pub fn is_command(line: &str) -> bool {
+        line.starts_with("aarch64-none-elf-") || line.starts_with("clang")
+    }
+
     pub fn from_command(dkp_bin_path: &str, build_directory: &str, command: &str) -> Self {
         // hopefully there are no spaces in the source paths...:)
         let mut iter = command.split_whitespace();
@@ -239,20 +698,626 @@ impl CompilerCommand {
         Self {
             directory: build_directory.to_string(),
             file,
-            command: format!("{dkp_bin_path}{command}"),
+            // clang is found in PATH, not in devkitA64
+            command: if command.starts_with("aarch64-none-elf-") {
+                format!("{dkp_bin_path}{command}")
+            } else {
+                command.to_string()
+            }
+            // the diagnostics format is for megaton, not for tools using the commands
+            .replace(&format!(" {}", diagnostic::JSON_FORMAT_FLAG), ""),
             output,
         }
     }
 }
 
+/// Write `sources.mk` with the sources in `source_dirs` that are not ignored
+///
+/// `.gitignore` and `.megatonignore` files are respected, and hidden files are skipped.
+/// The file is only rewritten if the list changed.
+pub fn write_source_list(
+    root_dir: &Path,
+    make_dir: &Path,
+    source_dirs: &[String],
+    extra_source_types: &[ExtraSourceType],
+) -> Result<(), Error> {
+    let mut c_files = BTreeSet::new();
+    let mut cpp_files = BTreeSet::new();
+    let mut s_files = BTreeSet::new();
+    let mut extra_objects = BTreeSet::new();
+    let mut process_source = |path: &Path| {
+        let Some(name) = path.file_name() else {
+            return;
+        };
+        let name = name.to_string_lossy().to_string();
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("c") => c_files.insert(name),
+            Some("cpp") => cpp_files.insert(name),
+            Some("s") => s_files.insert(name),
+            Some(ext) if extra_source_types.iter().any(|t| t.ext == ext) => {
+                let object = path.with_extension("o");
+                let Some(object) = object.file_name() else {
+                    return;
+                };
+                extra_objects.insert(object.to_string_lossy().to_string())
+            }
+            _ => return,
+        };
+    };
+    for dir in source_dirs {
+        let dir = root_dir.join(dir);
+        // a file listed in `sources` is compiled even if it's ignored
+        if dir.is_file() {
+            process_source(&dir);
+            continue;
+        }
+        let walker = ignore::WalkBuilder::new(&dir)
+            .require_git(false)
+            .add_custom_ignore_filename(".megatonignore")
+            .build();
+        for entry in walker {
+            let entry = entry.map_err(|e| {
+                Error::AccessDirectory(dir.display().to_string(), std::io::Error::other(e))
+            })?;
+            process_source(entry.path());
+        }
+    }
+
+    let join = |files: BTreeSet<String>| files.into_iter().collect::<Vec<_>>().join(" ");
+    let content = format!(
+        "# sources not ignored by .gitignore or .megatonignore\nCFILES := {}\nCPPFILES := {}\nSFILES := {}\nEXTRA_OFILES := {}\n",
+        join(c_files),
+        join(cpp_files),
+        join(s_files),
+        join(extra_objects)
+    );
+    let path = make_dir.join("sources.mk");
+    if std::fs::read_to_string(&path).ok().as_deref() != Some(content.as_str()) {
+        fs::write_file(&path, content)?;
+        verboseln!(1, "Saved", "`{}`", path.display());
+    }
+    Ok(())
+}
+
+/// Parse the prerequisites from a dependency file generated with `-MMD -MP`
+///
+/// The file has the rule for the object, then an empty rule for each header.
+/// Escaped spaces in paths are not supported.
+pub(crate) fn parse_dependency_file(content: &str) -> Vec<String> {
+    content
+        .replace("\\\n", " ")
+        .lines()
+        .filter_map(|rule| rule.split_once(": "))
+        .flat_map(|(_, prerequisites)| prerequisites.split_whitespace().map(str::to_string))
+        .collect()
+}
+
+/// Remove the objects in `build_dir` whose source no longer exists
+///
+/// The source is the first prerequisite in the dependency file of the object, so objects
+/// of sources outside of the source directories are kept as long as the source exists.
+/// Objects without a dependency file are kept. Returns the number of objects removed.
+pub fn remove_stale_objects(build_dir: &Path) -> Result<usize, Error> {
+    let entries = std::fs::read_dir(build_dir)
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    let mut count = 0;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?
+            .path();
+        if path.extension().and_then(|x| x.to_str()) != Some("d") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        let Some(source) = parse_dependency_file(&content).into_iter().next() else {
+            continue;
+        };
+        if build_dir.join(&source).exists() {
+            continue;
+        }
+        for extension in ["o", "dwo", "d"] {
+            let file = path.with_extension(extension);
+            if file.exists() {
+                std::fs::remove_file(&file)
+                    .map_err(|e| Error::AccessFile(file.display().to_string(), e))?;
+            }
+        }
+        verboseln!(
+            1,
+            "Removed",
+            "object of `{}`, which no longer exists",
+            source
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The file in the build directory with the content hashes for `hash-based`
+const SOURCE_HASHES: &str = "source.hashes";
+
+/// Make objects whose inputs have the same content as the last build up to date for make
+///
+/// For each object with a dependency file, the source and headers are hashed. If the hash
+/// is the same as the one saved by [`save_source_hashes`], the modified time of the object
+/// is bumped to the newest input, so make doesn't compile it again just because the inputs
+/// were touched.
+pub fn refresh_unchanged_objects(build_dir: &Path) -> Result<(), Error> {
+    let hashes_path = build_dir.join(SOURCE_HASHES);
+    let Ok(saved) = std::fs::read_to_string(&hashes_path) else {
+        return Ok(());
+    };
+    for line in saved.lines() {
+        let Some((object, saved_hash)) = line.split_once(' ') else {
+            continue;
+        };
+        let object_path = build_dir.join(object);
+        let Some(object_time) = fs::get_modified_time(&object_path) else {
+            continue;
+        };
+        let Some((hash, newest_time)) = hash_object_inputs(build_dir, object) else {
+            continue;
+        };
+        if hash != saved_hash || newest_time <= object_time {
+            continue;
+        }
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&object_path)
+            .map_err(|e| Error::AccessFile(object_path.display().to_string(), e))?;
+        file.set_modified(newest_time)
+            .map_err(|e| Error::AccessFile(object_path.display().to_string(), e))?;
+        verboseln!(1, "Unchanged", "{}", object);
+    }
+    Ok(())
+}
+
+/// Save the content hashes of the inputs of every object in `build_dir`
+///
+/// This should only be called after make succeeded, so every object is built from
+/// the inputs that are hashed.
+pub fn save_source_hashes(build_dir: &Path) -> Result<(), Error> {
+    let entries = std::fs::read_dir(build_dir)
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    let mut hashes = BTreeMap::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?
+            .path();
+        if path.extension().and_then(|x| x.to_str()) != Some("o") {
+            continue;
+        }
+        let Some(object) = path.file_name() else {
+            continue;
+        };
+        let object = object.to_string_lossy().to_string();
+        if let Some((hash, _)) = hash_object_inputs(build_dir, &object) {
+            hashes.insert(object, hash);
+        }
+    }
+    let content = hashes
+        .into_iter()
+        .map(|(object, hash)| format!("{object} {hash}\n"))
+        .collect::<String>();
+    fs::write_file(build_dir.join(SOURCE_HASHES), content)
+}
+
+/// Hash the inputs of `object` listed in its dependency file
+///
+/// Returns the hash and the newest modified time of the inputs, or `None` if
+/// the dependency file or an input can't be read.
+fn hash_object_inputs(build_dir: &Path, object: &str) -> Option<(String, SystemTime)> {
+    let dependency_file = build_dir.join(object).with_extension("d");
+    let content = std::fs::read_to_string(dependency_file).ok()?;
+    let mut hasher = Sha256::new();
+    let mut newest_time = SystemTime::UNIX_EPOCH;
+    for input in parse_dependency_file(&content) {
+        let path = build_dir.join(&input);
+        let data = std::fs::read(&path).ok()?;
+        newest_time = newest_time.max(fs::get_modified_time(&path)?);
+        hasher.update(input.as_bytes());
+        hasher.update(Sha256::digest(data));
+    }
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Some((hash, newest_time))
+}
+
+/// Get the name of the object for `source` (relative to `root_dir`)
+///
+/// Fails if the file doesn't exist or is not a source.
+pub fn source_object(
+    root_dir: &Path,
+    source: &str,
+    extra_source_types: &[ExtraSourceType],
+) -> Result<String, Error> {
+    let path = root_dir.join(source);
+    if !path.exists() {
+        return Err(Error::InvalidSource(
+            source.to_string(),
+            "the file does not exist".to_string(),
+        ));
+    }
+    if !is_source(&path, extra_source_types) {
+        return Err(Error::InvalidSource(
+            source.to_string(),
+            "only .c, .cpp, .s and `extra-source-types` files are compiled".to_string(),
+        ));
+    }
+    path.with_extension("o")
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| Error::InvalidSource(source.to_string(), "no file name".to_string()))
+}
+
+/// Get the command make would run to compile `object` in `build_dir`, without compiling
+///
+/// `makefile` is the content of the Makefile, which is passed to make through stdin,
+/// so nothing is written. The object is treated as out of date (`make -n -B`).
+pub fn dry_compile_command(
+    build_dir: &Path,
+    makefile: &str,
+    object: &str,
+    dkp_bin_path: &str,
+) -> Result<CompilerCommand, Error> {
+    let build_dir_str = build_dir.display().to_string();
+    let args = [
+        "--no-print-directory",
+        "V=1",
+        "-n",
+        "-B",
+        "-C",
+        &build_dir_str,
+        "-f",
+        "-",
+        object,
+    ];
+    let command = format!("make {:?}", args);
+    verboseln!(1, "Running", "make {}", args.join(" "));
+    let mut child = process::spawn(
+        Command::new("make")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    if let Some(mut stdin) = child.child.stdin.take() {
+        stdin
+            .write_all(makefile.as_bytes())
+            .map_err(|e| Error::Subprocess(command.clone(), "cannot write stdin".to_string(), e))?;
+    }
+    let output = child.wait_with_output()?;
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        errorln!("Error", "{}", line);
+    }
+    if !output.status.success() {
+        return Err(Error::MakeError);
+    }
+    let build_dir_abs = build_dir
+        .canonicalize()
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| CompilerCommand::is_command(line))
+        .map(|line| {
+            CompilerCommand::from_command(dkp_bin_path, &build_dir_abs.display().to_string(), line)
+        })
+        .ok_or_else(|| {
+            Error::InvalidSource(
+                object.to_string(),
+                "make has no command to compile it".to_string(),
+            )
+        })
+}
+
+/// Check if `target` is up to date in `build_dir`, without building anything (`make -q`)
+///
+/// An error in the Makefile or a missing rule is treated as out of date, since the
+/// build reports it anyway.
+pub fn is_target_up_to_date(
+    build_dir: &Path,
+    makefile_path: &str,
+    target: &str,
+    extra_args: &[String],
+) -> Result<bool, Error> {
+    let build_dir_str = build_dir.display().to_string();
+    let mut args = vec![
+        "--no-print-directory",
+        "-q",
+        "-C",
+        &build_dir_str,
+        "-f",
+        makefile_path,
+    ];
+    args.extend(extra_args.iter().map(String::as_str));
+    args.push(target);
+    verboseln!(2, "Running", "make {}", args.join(" "));
+    let output = process::output(
+        Command::new("make")
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()),
+    )?;
+    Ok(output.status.success())
+}
+
+/// Run only the preprocessor of `command`, and write the output to `output`
+///
+/// The flags are the same as the compile command. Only `-c`, `-o` and the options
+/// for the dependency file are removed, so the files from the build are not touched.
+pub fn preprocess(command: &CompilerCommand, output: &Path) -> Result<(), Error> {
+    // hopefully there are no spaces in the source paths...:)
+    let mut iter = command.command.split_whitespace();
+    let program = iter.next().unwrap_or_default();
+    let mut args = Vec::new();
+    while let Some(arg) = iter.next() {
+        match arg {
+            // the error filter, if any
+            "|" => break,
+            "-c" | "-o" | "-MF" | "-MT" | "-MQ" => {
+                iter.next();
+            }
+            "-MMD" | "-MD" | "-MP" => {}
+            _ => args.push(arg.to_string()),
+        }
+    }
+    args.extend([
+        "-E".to_string(),
+        command.file.clone(),
+        "-o".to_string(),
+        output.display().to_string(),
+    ]);
+    verboseln!(1, "Running", "{} {}", program, args.join(" "));
+    let result = process::output(
+        Command::new(program)
+            .args(&args)
+            .current_dir(&command.directory),
+    )?;
+    for line in String::from_utf8_lossy(&result.stderr).lines() {
+        errorln!("Error", "{}", line);
+    }
+    if !result.status.success() {
+        return Err(Error::InvalidSource(
+            command.file.clone(),
+            format!("the preprocessor failed: {}", result.status),
+        ));
+    }
+    Ok(())
+}
+
+/// Explain why make would compile `source` (relative to `root_dir`) in `build_dir`
+///
+/// This checks the same things as make: if the object exists, and if any input in the
+/// dependency file from the last build is missing or newer than the object. If `hash_based`,
+/// newer inputs are ignored when the hash of all inputs is the saved one, like
+/// [`refresh_unchanged_objects`]. Returns the reasons, or an empty list if the object is up to date.
+pub fn explain_rebuild(
+    root_dir: &Path,
+    build_dir: &Path,
+    source: &str,
+    extra_source_types: &[ExtraSourceType],
+    hash_based: bool,
+) -> Result<Vec<String>, Error> {
+    let path = root_dir.join(source);
+    let object = source_object(root_dir, source, extra_source_types)?;
+    let object_path = build_dir.join(&object);
+    let Some(object_time) = fs::get_modified_time(&object_path) else {
+        return Ok(vec![format!("`{}` does not exist", object)]);
+    };
+    let dependency_file = object_path.with_extension("d");
+    let Ok(content) = std::fs::read_to_string(&dependency_file) else {
+        // without the dependency file, only the source is a prerequisite
+        if fs::get_modified_time(&path).is_some_and(|time| time > object_time) {
+            return Ok(vec![format!("the source is newer than `{}`", object)]);
+        }
+        return Ok(Vec::new());
+    };
+
+    let mut reasons = Vec::new();
+    let mut newer = Vec::new();
+    for input in parse_dependency_file(&content) {
+        match fs::get_modified_time(&build_dir.join(&input)) {
+            None => reasons.push(format!("`{}` no longer exists", input)),
+            Some(time) if time > object_time => newer.push(input),
+            Some(_) => {}
+        }
+    }
+    // a missing input can't be hashed, so it's compiled even if hash-based
+    let hash_unchanged = reasons.is_empty() && hash_based && {
+        let saved = std::fs::read_to_string(build_dir.join(SOURCE_HASHES)).unwrap_or_default();
+        let saved_hash = saved
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(name, _)| *name == object)
+            .map(|(_, hash)| hash);
+        let hash = hash_object_inputs(build_dir, &object).map(|(hash, _)| hash);
+        saved_hash.is_some() && saved_hash == hash.as_deref()
+    };
+    // with an unchanged hash, the object is refreshed before make runs
+    if !hash_unchanged {
+        reasons.extend(
+            newer
+                .into_iter()
+                .map(|input| format!("`{}` is newer than `{}`", input, object)),
+        );
+    }
+    Ok(reasons)
+}
+
+/// Get the objects to compile for the files changed since `rev` in git
+///
+/// Changed sources in `source_dirs` are compiled, and so are the sources that include a
+/// changed header, according to the dependency files from the last build.
+/// Other changes (like to the config) are not detected.
+pub fn objects_changed_since(
+    root_dir: &Path,
+    build_dir: &Path,
+    rev: &str,
+    source_dirs: &[String],
+    extra_source_types: &[ExtraSourceType],
+) -> Result<Vec<String>, Error> {
+    // outside of a repository, git diff compares paths instead of failing
+    run_git(root_dir, &["rev-parse", "--git-dir"]).map_err(|_| {
+        Error::InvalidOption(format!(
+            "`--since` requires the project to be in a git repository: {}",
+            root_dir.display()
+        ))
+    })?;
+    let changed = run_git(root_dir, &["diff", "--name-only", "--relative", rev])?;
+
+    let root_dir_abs = root_dir
+        .canonicalize()
+        .map_err(|e| Error::AccessDirectory(root_dir.display().to_string(), e))?;
+    let source_dirs = source_dirs
+        .iter()
+        .map(|dir| normalize_path(&root_dir_abs.join(dir)))
+        .collect::<Vec<_>>();
+    let changed = changed
+        .lines()
+        .map(|line| normalize_path(&root_dir_abs.join(line)))
+        .collect::<BTreeSet<_>>();
+
+    let mut objects = BTreeSet::new();
+    for path in &changed {
+        if is_source(path, extra_source_types)
+            && path.exists()
+            && source_dirs.iter().any(|dir| path.starts_with(dir))
+        {
+            if let Some(name) = path.with_extension("o").file_name() {
+                objects.insert(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    // dependents of changed headers
+    let build_dir_abs = build_dir
+        .canonicalize()
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    let entries = std::fs::read_dir(&build_dir_abs)
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?
+            .path();
+        if path.extension().and_then(|x| x.to_str()) != Some("d") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+        let depends_on_changed = parse_dependency_file(&content)
+            .iter()
+            .any(|input| changed.contains(&normalize_path(&build_dir_abs.join(input))));
+        if depends_on_changed {
+            if let Some(name) = path.with_extension("o").file_name() {
+                objects.insert(name.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    Ok(objects.into_iter().collect())
+}
+
+/// Get the number of uncommitted changes (including untracked files) in `root_dir`
+///
+/// Returns `None` if `root_dir` is not in a git repository.
+pub fn uncommitted_changes(root_dir: &Path) -> Option<usize> {
+    run_git(root_dir, &["rev-parse", "--git-dir"]).ok()?;
+    let status = run_git(root_dir, &["status", "--porcelain", "--", "."]).ok()?;
+    Some(status.lines().count())
+}
+
+/// Run git in `root_dir` and get the output
+fn run_git(root_dir: &Path, args: &[&str]) -> Result<String, Error> {
+    let command = format!("git {}", args.join(" "));
+    verboseln!(1, "Running", "{}", command);
+    let output = process::output(Command::new("git").args(args).current_dir(root_dir))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::InvalidOption(format!(
+            "`{}` failed: {}",
+            command,
+            stderr.lines().next().unwrap_or_default()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Read the file passed to `--sources-from`
+///
+/// Each line is a source file relative to `root_dir`. Blank lines and `#` comments are skipped.
+/// Returns the object targets to make, and the directories of sources that are outside of
+/// `source_dirs`, which need to be added to `VPATH`.
+pub fn read_sources_list(
+    root_dir: &Path,
+    list_path: &Path,
+    source_dirs: &[String],
+    extra_source_types: &[ExtraSourceType],
+    allow_external: bool,
+) -> Result<(Vec<String>, Vec<String>), Error> {
+    let content = std::fs::read_to_string(list_path)
+        .map_err(|e| Error::AccessFile(list_path.display().to_string(), e))?;
+    let source_dirs = source_dirs
+        .iter()
+        .filter_map(|dir| root_dir.join(dir).canonicalize().ok())
+        .collect::<Vec<_>>();
+
+    let mut objects = Vec::new();
+    let mut external_dirs = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let path = root_dir
+            .join(line)
+            .canonicalize()
+            .map_err(|e| Error::AccessFile(line.to_string(), e))?;
+        if !is_source(&path, extra_source_types) {
+            return Err(Error::InvalidSource(
+                line.to_string(),
+                "only .c, .cpp, .s and `extra-source-types` files can be compiled".to_string(),
+            ));
+        }
+        let object = path.with_extension("o");
+        if let Some(name) = object.file_name() {
+            objects.push(name.to_string_lossy().to_string());
+        }
+        if source_dirs.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        if !allow_external {
+            return Err(Error::InvalidSource(
+                line.to_string(),
+                "it is not in any of the source directories. Use `--allow-external` to compile it anyway".to_string(),
+            ));
+        }
+        if let Some(dir) = path.parent() {
+            let dir = dir.display().to_string();
+            if !external_dirs.contains(&dir) {
+                external_dirs.push(dir);
+            }
+        }
+    }
+
+    Ok((objects, external_dirs))
+}
+
+/// Invoke make on `targets` in `build_dir`
+///
+/// `extra_args` are passed to make before the targets, such as variable overrides.
+/// Returns the diagnostics the compiler emitted, with the files relative to `root_dir`.
 pub fn invoke_make<SRoot, SBuild>(
     root_dir: SRoot,
     build_dir: SBuild,
     makefile_path: &str,
-    target: &str,
+    targets: &[String],
+    extra_args: &[String],
     dkp_bin_path: &str,
     save_compiler_commands: bool,
-) -> Result<(), Error>
+) -> Result<Vec<Diagnostic>, Error>
 where
     SRoot: AsRef<Path>,
     SBuild: AsRef<Path>,
@@ -260,9 +1325,13 @@ where
     let root_dir = root_dir.as_ref();
     let build_dir = build_dir.as_ref();
     let j_flag = format!("-j{}", num_cpus::get());
+    let target = match targets {
+        [target] => target.clone(),
+        _ => format!("{} targets", targets.len()),
+    };
     infoln!("Making", "{}", target);
     let build_dir_str = build_dir.display().to_string();
-    let args = vec![
+    let mut args = vec![
         "--no-print-directory",
         "V=1",
         &j_flag,
@@ -270,15 +1339,25 @@ where
         &build_dir_str,
         "-f",
         makefile_path,
-        target,
     ];
-    let command = format!("make {:?}", args);
-    let mut child = Command::new("make")
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| Error::Subprocess(command.clone(), "cannot spawn child".to_string(), e))?;
+    args.extend(extra_args.iter().map(String::as_str));
+    args.extend(targets.iter().map(String::as_str));
+    verboseln!(1, "Running", "make {}", args.join(" "));
+    for key in ["DEVKITPRO", "PATH"] {
+        verboseln!(
+            2,
+            "Env",
+            "{}={}",
+            key,
+            std::env::var(key).unwrap_or_default()
+        );
+    }
+    let mut child = process::spawn(
+        Command::new("make")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
 
     // load compiler commands
     let mut compiler_commands = BTreeMap::new();
@@ -286,71 +1365,108 @@ where
     if save_compiler_commands && cc_json_path.exists() {
         let cc_json = std::fs::read_to_string(&cc_json_path)
             .map_err(|e| Error::AccessFile(cc_json_path.display().to_string(), e))?;
-        if let Ok(cc_vec) = serde_json::from_str::<Vec<CompilerCommand>>(&cc_json) {
-            for command in cc_vec {
-                compiler_commands.insert(command.file.clone(), command);
+        match serde_json::from_str::<Vec<CompilerCommand>>(&cc_json) {
+            Ok(cc_vec) => {
+                for command in cc_vec {
+                    compiler_commands.insert(command.file.clone(), command);
+                }
+            }
+            Err(e) => {
+                let detail = if print::verbose_level() >= 1 {
+                    e.to_string()
+                } else {
+                    format!("{:?} error", e.classify()).to_lowercase()
+                };
+                let error =
+                    Error::InvalidCompileCommands(cc_json_path.display().to_string(), detail);
+                hintln!("Warning", "{}", error);
+                hintln!(
+                    "Warning",
+                    "Commands for files not compiled in this build will be missing from compile_commands.json."
+                );
             }
         }
     }
 
-    let build_dir_abs = build_dir.canonicalize().map_err(|e| {
-        Error::AccessDirectory(build_dir.display().to_string(), e)
-    })?;
-    let root_dir_abs = root_dir.canonicalize().map_err(|e| {
-        Error::AccessDirectory(root_dir.display().to_string(), e)
-    })?;
+    let build_dir_abs = build_dir
+        .canonicalize()
+        .map_err(|e| Error::AccessDirectory(build_dir.display().to_string(), e))?;
+    let root_dir_abs = root_dir
+        .canonicalize()
+        .map_err(|e| Error::AccessDirectory(root_dir.display().to_string(), e))?;
     let cc_build_path = build_dir_abs.display().to_string();
 
-    if let Some(stdout) = child.stdout.take() {
+    if let Some(stdout) = child.child.stdout.take() {
         let stdout = BufReader::new(stdout);
-        for line in stdout.lines() {
-            if let Ok(line) = line {
-                // hide some outputs
-                if line.starts_with("built ...") {
-                    continue;
-                }
-                if line.ends_with("up to date.") {
-                    continue;
-                }
-                if line.starts_with("aarch64-none-elf-") {
-                    // compiler command
-                    let compiler_command =
+        for line in stdout.lines().map_while(Result::ok) {
+            // hide some outputs
+            if line.starts_with("built ...") {
+                continue;
+            }
+            if line.ends_with("up to date.") {
+                continue;
+            }
+            if CompilerCommand::is_command(&line) {
+                // compiler command
+                verboseln!(2, "Command", "{}", line);
+                let compiler_command =
                     CompilerCommand::from_command(dkp_bin_path, &cc_build_path, &line);
-                    if let Some(file_path) = pathdiff::diff_paths(Path::new(&compiler_command.file), &root_dir_abs) {
-                        infoln!("Compiling", "{}", file_path.display());
-                    }
-                    compiler_commands.insert(compiler_command.file.clone(), compiler_command);
-                    continue;
-                }
-                if let Some(line) = line.strip_prefix("linking ") {
-                    infoln!("Linking", "{}", line);
+                // the file is relative to the build directory if `relative-paths` is enabled
+                let file_path = normalize_path(&build_dir_abs.join(&compiler_command.file));
+                if let Some(file_path) = pathdiff::diff_paths(&file_path, &root_dir_abs) {
+                    infoln!("Compiling", "{}", file_path.display());
                 }
-                // else {
-                //     infoln!("Make", "{}", line);
-                // }
+                compiler_commands.insert(compiler_command.file.clone(), compiler_command);
+                continue;
             }
+            if let Some(line) = line.strip_prefix("linking ") {
+                infoln!("Linking", "{}", line);
+            }
+            // else {
+            //     infoln!("Make", "{}", line);
+            // }
         }
     }
 
-    if let Some(stderr) = child.stderr.take() {
+    let mut diagnostics = Vec::new();
+    // the file is relative to the build directory, like the compiler commands
+    let relative_to_root = |mut diagnostic: Diagnostic| {
+        diagnostic.file = diagnostic.file.map(|file| {
+            let file_path = normalize_path(&build_dir_abs.join(&file));
+            match pathdiff::diff_paths(&file_path, &root_dir_abs) {
+                Some(file_path) => file_path.display().to_string(),
+                None => file,
+            }
+        });
+        diagnostic
+    };
+    if let Some(stderr) = child.child.stderr.take() {
         let stderr = BufReader::new(stderr);
-        for line in stderr.lines() {
-            if let Ok(line) = line {
-                // hide some outputs
-                if line.starts_with("make: ***") {
-                    continue;
-                }
-                if line == "compilation terminated." {
-                    continue;
+        for line in stderr.lines().map_while(Result::ok) {
+            // hide some outputs
+            if line.starts_with("make: ***") {
+                continue;
+            }
+            if line == "compilation terminated." {
+                continue;
+            }
+            // with the JSON format, each compiler prints one array of diagnostics
+            if let Some(json_diagnostics) = diagnostic::parse_json(&line) {
+                for json_diagnostic in json_diagnostics {
+                    let json_diagnostic = relative_to_root(json_diagnostic);
+                    errorln!("Error", "{}", json_diagnostic);
+                    diagnostics.push(json_diagnostic);
                 }
-                errorln!("Error", "{}", line);
+                continue;
             }
+            if let Some(text_diagnostic) = diagnostic::parse_text(&line) {
+                diagnostics.push(relative_to_root(text_diagnostic));
+            }
+            errorln!("Error", "{}", line);
         }
     }
 
-    let status = child
-        .wait()
-        .map_err(|e| Error::Subprocess(command.clone(), "cannot wait for child".to_string(), e))?;
+    let status = child.wait()?;
     if !status.success() {
         return Err(Error::MakeError);
     }
@@ -358,6 +1474,7 @@ where
     infoln!("Finished", "{}", target);
 
     if save_compiler_commands {
+        // the map is ordered by file, so the output is deterministic
         let vec = compiler_commands.into_values().collect::<Vec<_>>();
 
         match serde_json::to_string_pretty(&vec) {
@@ -365,14 +1482,464 @@ where
                 errorln!("Error", "Failed to serialize compiler commands: {}", e);
             }
             Ok(json) => {
-                std::fs::write(&cc_json_path, json).map_err(|e| {
-                    Error::AccessFile(cc_json_path.display().to_string(), e)
-                })?;
+                fs::write_file(&cc_json_path, json)?;
                 infoln!("Saved", "compile_commands.json")
             }
         }
     }
 
+    Ok(diagnostics)
+}
+
+/// Compile the globs of the config option `key`
+///
+/// This is done before building, so an invalid glob fails the build before compiling.
+pub fn compile_globs(key: &str, patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| {
+            Error::ParseConfig(format!("invalid glob `{}` in `{}`: {}", pattern, key, e))
+        })?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| Error::ParseConfig(format!("invalid `{}`: {}", key, e)))
+}
 
+/// Fail if any source with warnings in `diagnostics` matches the `deny-warnings-in` globs
+///
+/// The objects (and split debug info) of the matching sources are removed, so they
+/// are compiled (and fail) again in the next build.
+pub fn check_denied_warnings(
+    build_dir: &Path,
+    diagnostics: &[Diagnostic],
+    globs: &GlobSet,
+) -> Result<(), Error> {
+    let warned_files = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.is_warning())
+        .filter_map(|diagnostic| diagnostic.file.as_ref())
+        .collect::<BTreeSet<_>>();
+    let mut count = 0;
+    for file in warned_files.iter().filter(|file| globs.is_match(file)) {
+        errorln!("Error", "Warnings are denied in `{}`", file);
+        count += 1;
+        if let Some(stem) = Path::new(file).file_stem() {
+            for extension in ["o", "dwo"] {
+                let object = build_dir.join(stem).with_extension(extension);
+                if object.exists() {
+                    std::fs::remove_file(&object)
+                        .map_err(|e| Error::AccessFile(object.display().to_string(), e))?;
+                }
+            }
+        }
+    }
+    if count > 0 {
+        return Err(Error::DeniedWarnings(count));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{test_dir, MemoryFileSystem};
+
+    /// Generate the Makefile for `config` (the content of Megaton.toml) in `root`
+    fn create_makefile(root: &Path, config: &str) -> Result<String, Error> {
+        let config = toml::from_str::<MegatonConfig>(config).unwrap();
+        let cli = MegatonHammer {
+            dir: Some(root.display().to_string()),
+            ..Default::default()
+        };
+        config.create_makefile(&cli, &root.join("build"))
+    }
+
+    /// Run make with `makefile` and `args` in `<root>/build`
+    ///
+    /// `switch_rules` is replaced with a stub, so devkitPro is not needed. The stub has the
+    /// devkitPro rule for `.s` files, which compiles with the host `cc`.
+    fn run_make(root: &Path, makefile: &str, args: &[&str]) -> std::process::Output {
+        let build_dir = root.join("build");
+        fs::write_file(build_dir.join("build.mk"), makefile).unwrap();
+        let devkitpro = root.join("devkitpro");
+        fs::write_file(
+            devkitpro.join("libnx/switch_rules"),
+            "LIBNX := /opt/devkitpro/libnx\nPORTLIBS := /opt/devkitpro/portlibs/switch\n\
+             %.o: %.s\n\t$(CC) -MMD -MP -MF $(DEPSDIR)/$*.d -x assembler-with-cpp $(CPPFLAGS) $(ASFLAGS) -c $< -o $@\n",
+        )
+        .unwrap();
+        Command::new("make")
+            .current_dir(&build_dir)
+            .arg("-s")
+            .arg("-f")
+            .arg("build.mk")
+            .arg(format!("DEVKITPRO={}", devkitpro.display()))
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Evaluate `variable` in `makefile` with make, like [`run_make`]
+    fn eval_make(root: &Path, makefile: &str, variable: &str) -> String {
+        let eval = format!("megaton-eval: ; @echo $({variable})");
+        let output = run_make(root, makefile, &["--eval", &eval, "megaton-eval"]);
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// A Megaton.toml with the required fields, followed by `make`
+    fn config(make: &str) -> String {
+        format!("[module]\nname = \"test\"\ntitle-id = 0x0100000000001000\n\n[make]\nentry = \"main\"\n{make}")
+    }
+
+    #[test]
+    fn objects_are_sorted() {
+        let dir = test_dir("sorted-objects");
+        for file in [
+            "src/zeta.cpp",
+            "src/b/beta.c",
+            "src/alpha.cpp",
+            "src/a/gamma.s",
+            "src/m.c",
+        ] {
+            fs::write_file(dir.join(file), "").unwrap();
+        }
+        let makefile = create_makefile(&dir, &config("sources = [\"src\"]")).unwrap();
+        let objects = eval_make(&dir, &makefile, "OFILES");
+        assert_eq!(objects, "alpha.o zeta.o beta.o m.o gamma.o");
+        // files created in a different order give the same objects
+        std::fs::remove_dir_all(dir.join("src")).unwrap();
+        for file in [
+            "src/m.c",
+            "src/a/gamma.s",
+            "src/alpha.cpp",
+            "src/b/beta.c",
+            "src/zeta.cpp",
+        ] {
+            fs::write_file(dir.join(file), "").unwrap();
+        }
+        assert_eq!(eval_make(&dir, &makefile, "OFILES"), objects);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn makefile_is_replaced_only_when_content_changes() {
+        let path = Path::new("target/megaton/debug/none/make/build.mk");
+        let fs = MemoryFileSystem::default();
+        assert!(is_makefile_changed(&fs, path, "all:"));
+        let fs = fs.file(path, "all:", 1);
+        assert!(!is_makefile_changed(&fs, path, "all:"));
+        assert!(is_makefile_changed(&fs, path, "all: main.o"));
+    }
+
+    #[test]
+    fn remove_stale_objects_keeps_objects_of_existing_sources() {
+        let dir = test_dir("stale-objects");
+        let build_dir = dir.join("build");
+        std::fs::create_dir_all(&build_dir).unwrap();
+        // a source outside of the source directories, like one from `sources` entries
+        let external = dir.join("external.cpp");
+        std::fs::write(&external, "").unwrap();
+        std::fs::write(
+            build_dir.join("external.d"),
+            format!("external.o: {}\n", external.display()),
+        )
+        .unwrap();
+        std::fs::write(build_dir.join("external.o"), "").unwrap();
+        // a source relative to the build directory that was deleted
+        std::fs::write(
+            build_dir.join("deleted.d"),
+            "deleted.o: ../src/deleted.cpp\n",
+        )
+        .unwrap();
+        std::fs::write(build_dir.join("deleted.o"), "").unwrap();
+        std::fs::write(build_dir.join("deleted.dwo"), "").unwrap();
+        // an object without a dependency file
+        std::fs::write(build_dir.join("prebuilt.o"), "").unwrap();
+
+        assert_eq!(remove_stale_objects(&build_dir).unwrap(), 1);
+        assert!(build_dir.join("external.o").exists());
+        assert!(build_dir.join("external.d").exists());
+        assert!(build_dir.join("prebuilt.o").exists());
+        assert!(!build_dir.join("deleted.o").exists());
+        assert!(!build_dir.join("deleted.dwo").exists());
+        assert!(!build_dir.join("deleted.d").exists());
+
+        std::fs::remove_file(&external).unwrap();
+        assert_eq!(remove_stale_objects(&build_dir).unwrap(), 1);
+        assert!(!build_dir.join("external.o").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_flag_file_skips_comments_and_blank_lines() {
+        let dir = test_dir("flag-file");
+        let path = dir.join("cflags.txt");
+        std::fs::write(
+            &path,
+            "# warnings\n-Wall\n\n   \n  -Wextra  \n\t# indented comment\n-DVALUE=\"a b\"\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_flag_file(path).unwrap(),
+            vec!["-Wall", "-Wextra", "-DVALUE=\"a b\""]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_flag_file_missing_is_error() {
+        let dir = test_dir("flag-file-missing");
+        assert!(matches!(
+            read_flag_file(dir.join("missing.txt")),
+            Err(Error::AccessFile(..))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn link_stamp_changes_with_the_linker_scripts() {
+        let dir = test_dir("link-stamp");
+        let scripts = ["a.ld", "b.ld", "c.ld"].map(|name| {
+            let path = dir.join(name);
+            std::fs::write(&path, name).unwrap();
+            path
+        });
+        let [a, b, c] = &scripts;
+        let stamp_path = dir.join("link.stamp");
+        let stamp = |ld_scripts: &[&PathBuf]| {
+            let ld_scripts = ld_scripts
+                .iter()
+                .map(|path| path.to_path_buf())
+                .collect::<Vec<_>>();
+            write_link_stamp(&dir, &ld_scripts).unwrap();
+            std::fs::read_to_string(&stamp_path).unwrap()
+        };
+
+        let original = stamp(&[a, b]);
+        // the same scripts don't rewrite the stamp, so the ELF is not relinked
+        let old_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(&stamp_path)
+            .unwrap()
+            .set_modified(old_time)
+            .unwrap();
+        assert_eq!(stamp(&[a, b]), original);
+        assert_eq!(fs::get_modified_time(&stamp_path), Some(old_time));
+
+        // added, removed, reordered
+        assert_ne!(stamp(&[a, b, c]), original);
+        assert_ne!(stamp(&[a]), original);
+        assert_ne!(stamp(&[b, a]), original);
+        assert_eq!(stamp(&[a, b]), original);
+        std::fs::write(b, "edited").unwrap();
+        assert_ne!(stamp(&[a, b]), original);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn duplicate_includes_and_defines_are_removed() {
+        let dir = test_dir("dedup");
+        fs::write_file(dir.join("src/main.cpp"), "").unwrap();
+        std::fs::create_dir_all(dir.join("include")).unwrap();
+        let config = config(
+            r#"
+sources = ["src", "src/"]
+includes = ["include", "include/"]
+defines = ["A=1", "B", "A=1", "A=2"]
+
+[make.profiles.foo]
+includes = ["include"]
+defines = ["B"]
+"#,
+        );
+        let config = toml::from_str::<MegatonConfig>(&config).unwrap();
+        let cli = MegatonHammer {
+            dir: Some(dir.display().to_string()),
+            options: crate::BuildOptions {
+                profile: Some("foo".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let makefile = config.create_makefile(&cli, &dir.join("build")).unwrap();
+        let root = dir.canonicalize().unwrap();
+        assert_eq!(
+            eval_make(&dir, &makefile, "INCLUDES"),
+            format!("{}/include", root.display())
+        );
+        // different values of the same define are kept
+        assert_eq!(eval_make(&dir, &makefile, "DEFINES"), "-DA=1 -DB -DA=2");
+        assert_eq!(eval_make(&dir, &makefile, "OFILES"), "main.o");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_include_directories_are_still_passed() {
+        let dir = test_dir("missing-include");
+        let config = config(
+            r#"
+includes = ["generated/include", "/opt/absolute/include"]
+"#,
+        );
+        let makefile = create_makefile(&dir, &config).unwrap();
+        let root = dir.canonicalize().unwrap();
+        assert_eq!(
+            eval_make(&dir, &makefile, "INCLUDES"),
+            format!("{}/generated/include /opt/absolute/include", root.display())
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn libraries_by_name_file_name_and_path() {
+        let dir = test_dir("libraries");
+        let config = config(
+            r#"
+libraries = ["foo", ":libfoo_custom.a", "libs/bar/libbar.a", "/opt/lib/libbaz.a"]
+"#,
+        );
+        let makefile = create_makefile(&dir, &config).unwrap();
+        let root = dir.canonicalize().unwrap();
+        let bar = format!("{}/libs/bar/libbar.a", root.display());
+        assert_eq!(
+            eval_make(&dir, &makefile, "LIBS"),
+            format!("-lfoo -l:libfoo_custom.a {bar} /opt/lib/libbaz.a")
+        );
+        // the paths are prerequisites of the ELF, so it's relinked when they change
+        assert_eq!(
+            eval_make(&dir, &makefile, "LIBRARY_FILES"),
+            format!("{bar} /opt/lib/libbaz.a")
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn library_and_system_includes_use_isystem() {
+        let dir = test_dir("isystem");
+        std::fs::create_dir_all(dir.join("include")).unwrap();
+        std::fs::create_dir_all(dir.join("third/include")).unwrap();
+        let config = config(
+            r#"
+includes = ["include"]
+system-includes = ["third/include"]
+"#,
+        );
+        let makefile = create_makefile(&dir, &config).unwrap();
+        let root = dir.canonicalize().unwrap().display().to_string();
+        assert_eq!(
+            eval_make(&dir, &makefile, "INCLUDE_FLAGS"),
+            format!(
+                "-I{root}/include -isystem /opt/devkitpro/portlibs/switch/include \
+                 -isystem /opt/devkitpro/libnx/include -isystem {root}/third/include"
+            )
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn clang_omits_gcc_only_flags() {
+        let dir = test_dir("clang");
+        let gcc = create_makefile(&dir, &config("")).unwrap();
+        let clang = create_makefile(&dir, &config("compiler = \"clang\"")).unwrap();
+        let flags = |makefile: &str| {
+            ["CFLAGS", "CXXFLAGS", "ASFLAGS", "LDFLAGS"]
+                .map(|variable| eval_make(&dir, makefile, variable))
+                .join(" ")
+        };
+        let gcc_flags = flags(&gcc);
+        assert!(gcc_flags.contains("-mtp=soft"));
+        assert!(gcc_flags.contains("-fpermissive"));
+        let clang_flags = flags(&clang);
+        assert!(!clang_flags.contains("-mtp=soft"));
+        assert!(!clang_flags.contains("-fpermissive"));
+        // the other default flags are the same
+        assert!(clang_flags.contains("-march=armv8-a+crc+crypto"));
+        assert!(clang_flags.contains("-fno-rtti"));
+        assert_eq!(
+            eval_make(&dir, &clang, "CC"),
+            "clang --target=aarch64-none-elf"
+        );
+        assert_eq!(
+            eval_make(&dir, &clang, "CXX"),
+            "clang++ --target=aarch64-none-elf"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sources_mix_files_and_directories() {
+        let dir = test_dir("source-entries");
+        for file in [
+            "src/main.cpp",
+            "src/util.c",
+            "extra/one.cpp",
+            "extra/two.cpp",
+            "notes.txt",
+        ] {
+            fs::write_file(dir.join(file), "").unwrap();
+        }
+        let makefile =
+            create_makefile(&dir, &config(r#"sources = ["src", "extra/one.cpp"]"#)).unwrap();
+        // only the listed file is compiled from `extra`
+        assert_eq!(eval_make(&dir, &makefile, "OFILES"), "main.o one.o util.o");
+        let root = dir.canonicalize().unwrap();
+        assert!(eval_make(&dir, &makefile, "VPATH")
+            .split_whitespace()
+            .any(|path| path == format!("{}/extra/", root.display())));
+
+        // a file that is not a source is an error
+        assert!(matches!(
+            create_makefile(&dir, &config(r#"sources = ["src", "notes.txt"]"#)),
+            Err(Error::ParseConfig(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn as_includes_are_only_for_assembly() {
+        let dir = test_dir("as-includes");
+        fs::write_file(dir.join("asm_inc/values.inc"), ".set VALUE, 42\n").unwrap();
+        fs::write_file(
+            dir.join("src/one.s"),
+            ".include \"values.inc\"\n.long VALUE\n",
+        )
+        .unwrap();
+        // the default flags are for the target, but this is assembled by the host
+        let config = config(
+            r#"
+no-default-flags = true
+sources = ["src"]
+as-includes = ["asm_inc"]
+"#,
+        );
+        let makefile = create_makefile(&dir, &config).unwrap();
+        let root = dir.canonicalize().unwrap();
+        assert_eq!(
+            eval_make(&dir, &makefile, "ASFLAGS"),
+            format!("-I{}/asm_inc", root.display())
+        );
+        assert!(!eval_make(&dir, &makefile, "CFLAGS").contains("asm_inc"));
+        let output = run_make(&dir, &makefile, &["one.o"]);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(dir.join("build/one.o").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn is_target_up_to_date_asks_make() {
+        let dir = test_dir("target-up-to-date");
+        fs::write_file(dir.join("build.mk"), "out: in\n\tcp in out\n").unwrap();
+        fs::write_file(dir.join("in"), "in").unwrap();
+        let up_to_date = || is_target_up_to_date(&dir, "build.mk", "out", &[]).unwrap();
+        assert!(!up_to_date());
+        assert!(!dir.join("out").exists());
+        std::fs::copy(dir.join("in"), dir.join("out")).unwrap();
+        assert!(up_to_date());
+        assert!(!is_target_up_to_date(&dir, "build.mk", "missing", &[]).unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
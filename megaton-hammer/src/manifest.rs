@@ -0,0 +1,90 @@
+//! The manifest of the build outputs, for tools that package or deploy them.
+//!
+//! Megaton writes it at `./target/megaton/<flavor>/<profile>/manifest.json` after a
+//! successful build, so the tools don't need to guess the file names.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::{fs, verboseln, MegatonConfig};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    pub module: String,
+    pub output_name: String,
+    pub flavor: String,
+    pub profile: String,
+    pub artifacts: Vec<Artifact>,
+}
+
+/// A file produced by the build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Artifact {
+    /// The kind of the output, like `elf`, `nso` or `npdm`
+    pub kind: String,
+    /// The absolute path
+    pub path: String,
+    pub size: u64,
+    /// The modified time, in seconds since the Unix epoch
+    pub modified: u64,
+}
+
+/// Read `manifest.json` in `target_dir`, written by the last successful build
+pub fn read_manifest(target_dir: &Path) -> Result<Manifest, Error> {
+    let path = target_dir.join("manifest.json");
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| Error::AccessFile(path.display().to_string(), std::io::Error::other(e)))
+}
+
+/// Write `manifest.json` in `target_dir` with the `artifacts` (kind and path) of the build
+pub fn write_manifest(
+    target_dir: &Path,
+    config: &MegatonConfig,
+    flavor: &str,
+    profile: &str,
+    artifacts: &[(&str, PathBuf)],
+) -> Result<(), Error> {
+    let artifacts = artifacts
+        .iter()
+        .map(|(kind, path)| {
+            let path = path
+                .canonicalize()
+                .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+            let metadata = path
+                .metadata()
+                .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            Ok(Artifact {
+                kind: kind.to_string(),
+                path: path.display().to_string(),
+                size: metadata.len(),
+                modified,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let manifest = Manifest {
+        module: config.module.name.clone(),
+        output_name: config.module.output_name().to_string(),
+        flavor: flavor.to_string(),
+        profile: profile.to_string(),
+        artifacts,
+    };
+    let path = target_dir.join("manifest.json");
+    let content = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| Error::AccessFile(path.display().to_string(), std::io::Error::other(e)))?;
+    fs::write_file(&path, content)?;
+    verboseln!(1, "Saved", "`{}`", path.display());
+    Ok(())
+}
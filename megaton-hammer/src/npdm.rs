@@ -0,0 +1,126 @@
+//! Generating the npdm file with devkitPro's `npdmtool`.
+//!
+//! Megaton puts the npdm at `./target/megaton/<flavor>/<profile>/main.npdm`. The hash of
+//! the npdm config is saved in `npdm.stamp` next to it, so the npdm is only generated
+//! again when the config changes.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::process;
+use crate::{fs, infoln, verboseln, MegatonConfig};
+
+/// The bundled npdm template, with `TITLE_ID_PLACEHOLDER` for the title id
+const TEMPLATE: &str = include_str!("./template.json");
+
+/// Generate `main.npdm` in `target_dir` from the bundled template
+///
+/// Nothing is done if the npdm exists and the config from the template (like
+/// the title id) is the same as the last time. If `npdm-template-sha256` is set,
+/// the template must have that hash.
+pub fn generate_npdm<STool, STarget>(
+    npdmtool: STool,
+    target_dir: STarget,
+    config: &MegatonConfig,
+) -> Result<(), Error>
+where
+    STool: AsRef<Path>,
+    STarget: AsRef<Path>,
+{
+    let npdmtool = npdmtool.as_ref();
+    let target_dir = target_dir.as_ref();
+    let app_json_path = target_dir.join("npdm-app.json");
+    if let Some(expected) = &config.module.npdm_template_sha256 {
+        let template_hash = sha256_hex(TEMPLATE);
+        if !template_hash.eq_ignore_ascii_case(expected.trim()) {
+            return Err(Error::ParseConfig(format!(
+                "`npdm-template-sha256` is `{}`, but the hash of the npdm template is `{}`",
+                expected, template_hash
+            )));
+        }
+    }
+    let app_json = TEMPLATE.replace("TITLE_ID_PLACEHOLDER", &config.module.title_id_hex());
+    let hash = sha256_hex(&app_json);
+    let npdm_path = target_dir.join("main.npdm");
+    let stamp_path = target_dir.join("npdm.stamp");
+    if npdm_path.exists()
+        && std::fs::read_to_string(&stamp_path).ok().as_deref() == Some(hash.as_str())
+    {
+        verboseln!(1, "Skipped", "main.npdm is up to date");
+        return Ok(());
+    }
+    fs::write_file(&app_json_path, app_json)?;
+
+    let args = vec![
+        app_json_path.display().to_string(),
+        npdm_path.display().to_string(),
+    ];
+    let command = format!("{} {}", npdmtool.display(), args.join(" "));
+    verboseln!(1, "Running", "{}", command);
+    let mut child = process::spawn(
+        Command::new(npdmtool)
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()),
+    )?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::NpdmError(status));
+    }
+    infoln!("Created", "main.npdm");
+    fs::write_file(&stamp_path, hash)?;
+
+    Ok(())
+}
+
+/// Get the SHA-256 of `content` as a lower-case hex string
+fn sha256_hex(content: &str) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(title_id: &str, defines: &str) -> MegatonConfig {
+        toml::from_str(&format!(
+            "[module]\nname = \"test\"\ntitle-id = {title_id}\n\n[make]\nentry = \"main\"\ndefines = {defines}\n"
+        ))
+        .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn npdm_is_only_generated_when_its_config_changes() {
+        let dir = fs::test_dir("npdm");
+        // `npdmtool <json> <npdm>` is stubbed with `cp`, so the npdm is the json
+        let npdmtool = Path::new("/bin/cp");
+        let npdm = dir.join("main.npdm");
+        // replace the npdm, to see if it's generated again
+        let generate = |config: &MegatonConfig| {
+            if npdm.exists() {
+                std::fs::write(&npdm, "old").unwrap();
+            }
+            generate_npdm(npdmtool, &dir, config).unwrap();
+            std::fs::read_to_string(&npdm).unwrap() != "old"
+        };
+
+        assert!(generate(&config("0x0100000000001000", "[]")));
+        // other config, like the build flags, doesn't affect the npdm
+        assert!(!generate(&config("0x0100000000001000", "[\"A=1\"]")));
+        assert!(generate(&config("0x0100000000002000", "[\"A=1\"]")));
+        assert!(std::fs::read_to_string(&npdm)
+            .unwrap()
+            .contains("0100000000002000"));
+        // a removed npdm is generated again
+        std::fs::remove_file(&npdm).unwrap();
+        assert!(generate(&config("0x0100000000002000", "[\"A=1\"]")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
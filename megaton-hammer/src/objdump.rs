@@ -0,0 +1,331 @@
+//! Parsing the dynamic symbols from `objdump -T` output and `.syms` files
+//!
+//! This is shared by the symbol check and the `symbols` command.
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::error::Error;
+use crate::process;
+use crate::{errorln, hintln, infoln, verboseln};
+
+/// Run `objdump -T` on `binary` and parse the dynamic symbols
+///
+/// A binary without a dynamic symbol table has no symbols.
+pub fn dump_symbols<SObjDump, SBinary>(
+    objdump: SObjDump,
+    binary: SBinary,
+) -> Result<BTreeSet<String>, Error>
+where
+    SObjDump: AsRef<Path>,
+    SBinary: AsRef<Path>,
+{
+    Ok(dump_symbol_table(objdump, binary)?.unwrap_or_default())
+}
+
+/// Run `objdump -T` on `binary` and parse the dynamic symbols
+///
+/// Returns `None` if the binary has no dynamic symbol table.
+pub fn dump_symbol_table<SObjDump, SBinary>(
+    objdump: SObjDump,
+    binary: SBinary,
+) -> Result<Option<BTreeSet<String>>, Error>
+where
+    SObjDump: AsRef<Path>,
+    SBinary: AsRef<Path>,
+{
+    run_objdump_dynamic(objdump.as_ref(), binary.as_ref(), |lines| {
+        let mut symbols = BTreeSet::new();
+        let has_table = parse_objdump_syms("(elf objdump output)", lines, &mut symbols)?;
+        Ok(has_table.then_some(symbols))
+    })
+}
+
+/// Run `objdump -T` on `binary` and get the dynamic symbols it defines (exports)
+pub fn dump_exports<SObjDump, SBinary>(
+    objdump: SObjDump,
+    binary: SBinary,
+) -> Result<BTreeSet<String>, Error>
+where
+    SObjDump: AsRef<Path>,
+    SBinary: AsRef<Path>,
+{
+    run_objdump_dynamic(objdump.as_ref(), binary.as_ref(), |lines| {
+        Ok(parse_objdump_exports(lines))
+    })
+}
+
+/// Run `objdump -T` on `binary` and parse the output with `parse`
+fn run_objdump_dynamic<T: Default>(
+    objdump: &Path,
+    binary: &Path,
+    parse: impl FnOnce(&mut dyn Iterator<Item = String>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let binary_path = binary.display().to_string();
+    let args = vec!["-T", &binary_path];
+    let command = format!("{} {}", objdump.display(), args.join(" "));
+    verboseln!(1, "Running", "{}", command);
+
+    let mut child = process::spawn(
+        Command::new(objdump)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+
+    let mut symbols = T::default();
+    if let Some(stdout) = child.child.stdout.take() {
+        let mut stdout = BufReader::new(stdout).lines().map_while(Result::ok);
+        symbols = parse(&mut stdout)?;
+    }
+
+    if let Some(stderr) = child.child.stderr.take() {
+        let stderr = BufReader::new(stderr);
+        for line in stderr.lines().map_while(Result::ok) {
+            errorln!("Error", "{}", line);
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::CheckError);
+    }
+
+    Ok(symbols)
+}
+
+/// Parse the symbols that are not `*UND*` from the output of `objdump -T`
+///
+/// The name is the last column, since a version column can come before it.
+pub fn parse_objdump_exports<Iter, Str>(raw_symbols: Iter) -> BTreeSet<String>
+where
+    Iter: IntoIterator<Item = Str>,
+    Str: AsRef<str>,
+{
+    let mut iter = raw_symbols.into_iter();
+    for line in iter.by_ref() {
+        if line.as_ref() == "DYNAMIC SYMBOL TABLE:" {
+            break;
+        }
+    }
+    let mut exports = BTreeSet::new();
+    for line in iter {
+        let line = line.as_ref();
+        let Some(rest) = line.get(25..) else {
+            continue;
+        };
+        let section = rest.split('\t').next().unwrap_or_default().trim();
+        if section.is_empty() || section == "*UND*" {
+            continue;
+        }
+        if let Some(name) = rest.split_whitespace().last() {
+            exports.insert(name.to_string());
+        }
+    }
+    exports
+}
+
+/// Parse the content of a `.syms` file
+///
+/// The file can either be the output of `objdump -T`, or a hand-maintained list
+/// with one symbol per line. If the `DYNAMIC SYMBOL TABLE:` marker is not found,
+/// the file is treated as a list, where blank lines and `#` comments are ignored.
+pub fn parse_syms_file(
+    id: &str,
+    content: &str,
+    output: &mut BTreeSet<String>,
+) -> Result<(), Error> {
+    if content.lines().any(|line| line == "DYNAMIC SYMBOL TABLE:") {
+        parse_objdump_syms(id, content.lines(), output)?;
+        return Ok(());
+    }
+
+    infoln!("Parsing", "{}", id);
+    let old_size = output.len();
+    for line in content.lines() {
+        let symbol = match line.split_once('#') {
+            Some((symbol, _)) => symbol,
+            None => line,
+        };
+        let symbol = symbol.trim();
+        if !symbol.is_empty() {
+            output.insert(symbol.to_string());
+        }
+    }
+
+    if output.len() == old_size {
+        hintln!("Warning", "No symbols found in `{}`", id);
+    }
+
+    Ok(())
+}
+
+/// Parse the output of `objdump -T` into `output`
+///
+/// Returns `false` if the output has no dynamic symbol table, which is when objdump
+/// prints `no symbols` under the table marker, or doesn't print the marker at all.
+/// A warning is printed in that case. The caller should not treat it as a binary that
+/// references no symbols, since that would make the check pass without checking anything.
+pub fn parse_objdump_syms<Iter, Str>(
+    id: &str,
+    raw_symbols: Iter,
+    output: &mut BTreeSet<String>,
+) -> Result<bool, Error>
+where
+    Iter: IntoIterator<Item = Str>,
+    Str: AsRef<str>,
+{
+    infoln!("Parsing", "{}", id);
+    let mut iter = raw_symbols.into_iter().peekable();
+    let old_size = output.len();
+    let has_marker = iter
+        .by_ref()
+        .any(|line| line.as_ref() == "DYNAMIC SYMBOL TABLE:");
+    // objdump prints "no symbols" under the marker for binaries that are not dynamic
+    let has_table = has_marker && iter.peek().is_none_or(|line| line.as_ref() != "no symbols");
+    if !has_table {
+        hintln!(
+            "Warning",
+            "`{}` has no dynamic symbol table. This usually means the module was not linked with `-Wl,--shared` and `-Wl,--export-dynamic`",
+            id
+        );
+        return Ok(false);
+    }
+
+    // Example
+    // # 0000000000000000      DF *UND*	0000000000000000 nnsocketGetPeerName
+    //                   ^ spaces      ^ this is a tag
+    // The name is the last column, since a version column can come before it
+
+    for line in iter {
+        let line = line.as_ref();
+        if line.len() <= 25 {
+            continue;
+        }
+        let symbol = line[25..]
+            .split_once(' ')
+            .and_then(|(_, rest)| rest.split_whitespace().last());
+        let symbol = match symbol {
+            Some(symbol) => symbol,
+            None => {
+                return Err(Error::InvalidObjdump(
+                    id.to_string(),
+                    format!("invalid line: {}", line),
+                ))
+            }
+        };
+        output.insert(symbol.to_string());
+    }
+
+    if output.len() == old_size {
+        hintln!("Warning", "No symbols found in `{}`", id);
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `objdump -T` of a module linked with `-shared -nostdlib -Wl,--export-dynamic`
+    const MODULE: &str = include_str!("../tests/fixtures/objdump-module.txt");
+    /// `objdump -T` of a shared object with symbol versions
+    const VERSIONED: &str = include_str!("../tests/fixtures/objdump-versioned.txt");
+    /// `objdump -T` of an object that is not dynamic
+    const NO_DYNSYM: &str = include_str!("../tests/fixtures/objdump-no-dynsym.txt");
+
+    fn parse_syms(content: &str) -> BTreeSet<String> {
+        let mut symbols = BTreeSet::new();
+        assert!(parse_objdump_syms("test", content.lines(), &mut symbols).unwrap());
+        symbols
+    }
+
+    fn set(symbols: &[&str]) -> BTreeSet<String> {
+        symbols.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_objdump_syms_module() {
+        assert_eq!(
+            parse_syms(MODULE),
+            set(&[
+                "_Z6helperi",
+                "_ZN2nn6socket11GetPeerNameEi",
+                "exported_value",
+                "my_main",
+                "nnsocketGetPeerName",
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_objdump_syms_versioned() {
+        assert_eq!(
+            parse_syms(VERSIONED),
+            set(&[
+                "_ITM_deregisterTMCloneTable",
+                "_ITM_registerTMCloneTable",
+                "__cxa_finalize",
+                "__gmon_start__",
+                "my_main",
+                "nnsocketGetPeerName",
+                "printf",
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_objdump_exports_skips_undefined() {
+        assert_eq!(
+            parse_objdump_exports(MODULE.lines()),
+            set(&["_Z6helperi", "exported_value", "my_main"])
+        );
+        assert_eq!(parse_objdump_exports(VERSIONED.lines()), set(&["my_main"]));
+    }
+
+    #[test]
+    fn parse_objdump_syms_no_dynsym() {
+        let mut symbols = BTreeSet::new();
+        assert!(!parse_objdump_syms("test", NO_DYNSYM.lines(), &mut symbols).unwrap());
+        assert!(symbols.is_empty());
+        // without the marker at all
+        assert!(!parse_objdump_syms("test", "".lines(), &mut symbols).unwrap());
+        assert!(symbols.is_empty());
+    }
+
+    fn parse_file(content: &str) -> BTreeSet<String> {
+        let mut symbols = BTreeSet::new();
+        parse_syms_file("test", content, &mut symbols).unwrap();
+        symbols
+    }
+
+    #[test]
+    fn parse_syms_file_plain_list() {
+        let content = "# known symbols\nnnsocketGetPeerName\n\n  _ZN2nn2os5SleepEv  # padded\n";
+        assert_eq!(
+            parse_file(content),
+            set(&["_ZN2nn2os5SleepEv", "nnsocketGetPeerName"])
+        );
+    }
+
+    #[test]
+    fn parse_syms_file_objdump() {
+        assert_eq!(parse_file(MODULE), parse_syms(MODULE));
+    }
+
+    #[test]
+    fn parse_syms_file_mixed_is_objdump() {
+        // with the marker, the lines before it are not symbols
+        let content = format!("my_extra_symbol\n{}", MODULE);
+        assert_eq!(parse_file(&content), parse_syms(MODULE));
+    }
+
+    #[test]
+    fn parse_syms_file_empty() {
+        assert!(parse_file("").is_empty());
+        assert!(parse_file("\n# only comments\n\n").is_empty());
+    }
+}
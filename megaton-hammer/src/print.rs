@@ -1,5 +1,5 @@
-use std::io::Write;
-use std::{cell::RefCell, io::IsTerminal};
+use std::io::{IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 static mut ENABLED: bool = true;
@@ -15,10 +15,23 @@ pub fn is_enabled() -> bool {
     unsafe { ENABLED }
 }
 
-thread_local! {
-    static STDOUT: RefCell<StandardStream> = RefCell::new(make_stdout());
+static mut VERBOSE_LEVEL: u8 = 0;
+/// Set the verbose level (the number of times `-v` is specified)
+#[inline]
+pub fn set_verbose_level(level: u8) {
+    unsafe {
+        VERBOSE_LEVEL = level;
+    }
 }
 
+#[inline]
+pub fn verbose_level() -> u8 {
+    unsafe { VERBOSE_LEVEL }
+}
+
+/// The stdout shared by all threads, so each status line is written while holding the lock
+static STDOUT: OnceLock<Mutex<StandardStream>> = OnceLock::new();
+
 fn make_stdout() -> StandardStream {
     let color_choice = if std::io::stdout().is_terminal() {
         ColorChoice::Auto
@@ -28,71 +41,151 @@ fn make_stdout() -> StandardStream {
     StandardStream::stdout(color_choice)
 }
 
-pub(crate) fn print_status_tag(color_spec: &ColorSpec, tag: &str) {
-    STDOUT.with_borrow_mut(|stdout| {
-        let _ = stdout.set_color(color_spec);
-        let _ = write!(stdout, "{:>12}", tag);
-        let _ = stdout.reset();
-        print!(" ");
-    });
+/// The kind of a status line, which decides its color and the level of its event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusKind {
+    Info,
+    Hint,
+    Error,
+    Verbose,
+}
+
+impl StatusKind {
+    /// The color of the status tag for this kind
+    pub fn color(self) -> StatusColor {
+        match self {
+            Self::Info => StatusColor::Green,
+            Self::Hint => StatusColor::Yellow,
+            Self::Error => StatusColor::Red,
+            Self::Verbose => StatusColor::Cyan,
+        }
+    }
+}
+
+/// The colors for the status tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusColor {
+    Green,
+    Yellow,
+    Red,
+    Cyan,
+    Blue,
+    Magenta,
+    White,
+}
+
+impl StatusColor {
+    fn spec(self) -> ColorSpec {
+        let color = match self {
+            Self::Green => Color::Green,
+            Self::Yellow => Color::Yellow,
+            Self::Red => Color::Red,
+            Self::Cyan => Color::Cyan,
+            Self::Blue => Color::Blue,
+            Self::Magenta => Color::Magenta,
+            Self::White => Color::White,
+        };
+        let mut x = ColorSpec::new();
+        x.set_fg(Some(color)).set_bold(true);
+        x
+    }
 }
 
-pub(crate) fn info_color() -> ColorSpec {
-    let mut x = ColorSpec::new();
-    x.set_fg(Some(Color::Green)).set_bold(true);
-    x
+/// Print a status line, and emit it as a `tracing` event if the feature is enabled
+///
+/// This is the backend of the `infoln!`, `hintln!`, `errorln!` and `verboseln!` macros.
+/// The event has the `status` and `file` fields, and the line itself as the message.
+pub fn print_status(kind: StatusKind, status: &str, args: std::fmt::Arguments, file: &str) {
+    #[cfg(feature = "tracing")]
+    emit_event(kind, status, &args.to_string(), file);
+    #[cfg(not(feature = "tracing"))]
+    let _ = file;
+
+    write_status(kind.color(), status, args);
 }
 
-pub(crate) fn hint_color() -> ColorSpec {
-    let mut x = ColorSpec::new();
-    x.set_fg(Some(Color::Yellow)).set_bold(true);
-    x
+/// Print a status line aligned with the others, with the tag in `color`
+///
+/// This is for status lines that don't fit the kinds of the macros. The `tracing`
+/// event (if the feature is enabled) is at the info level.
+pub fn status(status: &str, color: StatusColor, args: std::fmt::Arguments) {
+    #[cfg(feature = "tracing")]
+    emit_event(StatusKind::Info, status, &args.to_string(), "");
+
+    write_status(color, status, args);
+}
+
+fn write_status(color: StatusColor, status: &str, args: std::fmt::Arguments) {
+    if !is_enabled() {
+        return;
+    }
+    let mut stdout = STDOUT
+        .get_or_init(|| Mutex::new(make_stdout()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let _ = stdout.set_color(&color.spec());
+    let _ = write!(stdout, "{:>12}", status);
+    let _ = stdout.reset();
+    let _ = writeln!(stdout, " {}", args);
 }
 
-pub(crate) fn error_color() -> ColorSpec {
-    let mut x = ColorSpec::new();
-    x.set_fg(Some(Color::Red)).set_bold(true);
-    x
+#[cfg(feature = "tracing")]
+fn emit_event(kind: StatusKind, status: &str, message: &str, file: &str) {
+    match kind {
+        StatusKind::Info => tracing::info!(status, file, "{}", message),
+        StatusKind::Hint => tracing::warn!(status, file, "{}", message),
+        StatusKind::Error => tracing::error!(status, file, "{}", message),
+        StatusKind::Verbose => tracing::debug!(status, file, "{}", message),
+    }
 }
 
 #[macro_export]
 macro_rules! infoln {
     ($status:expr, $($args:tt)*) => {
-        {
-            use $crate::print::*;
-            if is_enabled() {
-                let status = { $status };
-                print_status_tag(&info_color(), status);
-                println!($($args)*);
-            }
-        }
+        $crate::print::print_status(
+            $crate::print::StatusKind::Info,
+            { $status },
+            format_args!($($args)*),
+            file!(),
+        )
     };
 }
 
 #[macro_export]
 macro_rules! errorln {
     ($status:expr, $($args:tt)*) => {
-        {
-            use $crate::print::*;
-            if is_enabled() {
-                let status = { $status };
-                print_status_tag(&error_color(), status);
-                println!($($args)*);
-            }
-        }
+        $crate::print::print_status(
+            $crate::print::StatusKind::Error,
+            { $status },
+            format_args!($($args)*),
+            file!(),
+        )
     };
 }
 
 #[macro_export]
 macro_rules! hintln {
     ($status:expr, $($args:tt)*) => {
-        {
-            use $crate::print::*;
-            if is_enabled() {
-                let status = { $status };
-                print_status_tag(&hint_color(), status);
-                println!($($args)*);
-            }
+        $crate::print::print_status(
+            $crate::print::StatusKind::Hint,
+            { $status },
+            format_args!($($args)*),
+            file!(),
+        )
+    };
+}
+
+/// Print a status line only if the verbose level is at least `$level`
+#[macro_export]
+macro_rules! verboseln {
+    ($level:expr, $status:expr, $($args:tt)*) => {
+        if $crate::print::verbose_level() >= $level {
+            $crate::print::print_status(
+                $crate::print::StatusKind::Verbose,
+                { $status },
+                format_args!($($args)*),
+                file!(),
+            )
         }
     };
 }
@@ -0,0 +1,118 @@
+//! Spawning child processes
+//!
+//! With `--trace-exec`, every command line is printed when it's spawned, and its
+//! exit status and duration are printed when it's waited for. This is for reproducing
+//! a tool invocation by hand, without the rest of the `--verbose` output.
+
+use std::process::{Child, Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::error::Error;
+use crate::hintln;
+
+static TRACE_EXEC: AtomicBool = AtomicBool::new(false);
+
+/// Set if the child processes are traced (`--trace-exec`)
+pub fn set_trace_exec(enabled: bool) {
+    TRACE_EXEC.store(enabled, Ordering::Relaxed);
+}
+
+fn is_trace_exec() -> bool {
+    TRACE_EXEC.load(Ordering::Relaxed)
+}
+
+/// A spawned child process
+pub struct ChildProcess {
+    pub child: Child,
+    command_line: String,
+    start: Instant,
+}
+
+impl ChildProcess {
+    /// Wait for the process to exit
+    pub fn wait(&mut self) -> Result<ExitStatus, Error> {
+        let status = self.child.wait().map_err(|e| {
+            Error::Subprocess(
+                self.command_line.clone(),
+                "cannot wait for child".to_string(),
+                e,
+            )
+        })?;
+        trace_exit(&self.command_line, self.start, status);
+        Ok(status)
+    }
+
+    /// Wait for the process to exit and collect the output that was piped
+    pub fn wait_with_output(self) -> Result<Output, Error> {
+        let Self {
+            child,
+            command_line,
+            start,
+        } = self;
+        let output = child.wait_with_output().map_err(|e| {
+            Error::Subprocess(command_line.clone(), "cannot wait for child".to_string(), e)
+        })?;
+        trace_exit(&command_line, start, output.status);
+        Ok(output)
+    }
+}
+
+/// Spawn `command`
+pub fn spawn(command: &mut Command) -> Result<ChildProcess, Error> {
+    let command_line = command_line(command);
+    if is_trace_exec() {
+        hintln!("Exec", "{}", command_line);
+    }
+    let start = Instant::now();
+    let child = command.spawn().map_err(|e| {
+        Error::Subprocess(command_line.clone(), "cannot spawn child".to_string(), e)
+    })?;
+    Ok(ChildProcess {
+        child,
+        command_line,
+        start,
+    })
+}
+
+/// Run `command` to completion and collect its output
+pub fn output(command: &mut Command) -> Result<Output, Error> {
+    let command_line = command_line(command);
+    if is_trace_exec() {
+        hintln!("Exec", "{}", command_line);
+    }
+    let start = Instant::now();
+    let output = command.output().map_err(|e| {
+        Error::Subprocess(command_line.clone(), "cannot spawn child".to_string(), e)
+    })?;
+    trace_exit(&command_line, start, output.status);
+    Ok(output)
+}
+
+fn trace_exit(command_line: &str, start: Instant, status: ExitStatus) {
+    if is_trace_exec() {
+        hintln!(
+            "Exec",
+            "`{}` exited with {} after {:.2}s",
+            command_line,
+            status,
+            start.elapsed().as_secs_f64()
+        );
+    }
+}
+
+/// Get the command line of `command`, with the arguments that have spaces quoted
+pub fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{arg}'")
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,99 @@
+//! Packing the RomFS with devkitPro's `build_romfs`.
+//!
+//! Megaton puts the packed RomFS at `./target/megaton/<flavor>/<profile>/romfs.bin`
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::Romfs;
+use crate::error::Error;
+use crate::fs::{FileSystem, RealFileSystem};
+use crate::process;
+use crate::{infoln, verboseln};
+
+/// Pack the RomFS directory into `romfs.bin` in `target_dir`
+///
+/// The RomFS is only repacked if something in the directory changed since the last pack.
+/// Returns the path to `romfs.bin`.
+pub fn build_romfs<SRoot, STarget, STool>(
+    root_dir: SRoot,
+    target_dir: STarget,
+    build_romfs: STool,
+    romfs: &Romfs,
+) -> Result<PathBuf, Error>
+where
+    SRoot: AsRef<Path>,
+    STarget: AsRef<Path>,
+    STool: AsRef<Path>,
+{
+    let romfs_dir = root_dir.as_ref().join(&romfs.dir);
+    if !romfs_dir.is_dir() {
+        return Err(Error::AccessDirectory(
+            romfs_dir.display().to_string(),
+            std::io::ErrorKind::NotFound.into(),
+        ));
+    }
+    let romfs_bin = target_dir.as_ref().join("romfs.bin");
+    if is_romfs_up_to_date(&RealFileSystem, &romfs_dir, &romfs_bin) {
+        return Ok(romfs_bin);
+    }
+
+    infoln!("Packing", "{}", romfs_dir.display());
+    let args = vec![
+        romfs_dir.display().to_string(),
+        romfs_bin.display().to_string(),
+    ];
+    let build_romfs = build_romfs.as_ref();
+    let command = format!("{} {}", build_romfs.display(), args.join(" "));
+    verboseln!(1, "Running", "{}", command);
+    let mut child = process::spawn(
+        Command::new(build_romfs)
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()),
+    )?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::RomfsError(status));
+    }
+    infoln!("Created", "romfs.bin");
+
+    Ok(romfs_bin)
+}
+
+/// Check if `romfs_bin` is newer than everything in `romfs_dir`
+fn is_romfs_up_to_date(fs: &impl FileSystem, romfs_dir: &Path, romfs_bin: &Path) -> bool {
+    let dir_modified_time = fs.get_newest_modified_time(romfs_dir);
+    verboseln!(
+        2,
+        "Modified",
+        "romfs: {:?}, romfs.bin: {:?}",
+        dir_modified_time,
+        fs.get_modified_time(romfs_bin)
+    );
+    fs.is_up_to_date(romfs_bin, dir_modified_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFileSystem;
+
+    #[test]
+    fn romfs_is_packed_again_when_anything_inside_is_newer() {
+        let dir = Path::new("romfs");
+        let bin = Path::new("target/romfs.bin");
+        let fs = MemoryFileSystem::default()
+            .dir(dir, 1)
+            .dir("romfs/data", 2)
+            .file("romfs/data/a.bin", "", 3);
+        assert!(!is_romfs_up_to_date(&fs, dir, bin));
+        let fs = fs.file(bin, "", 3);
+        assert!(is_romfs_up_to_date(&fs, dir, bin));
+        let fs = fs.file("romfs/data/a.bin", "", 4);
+        assert!(!is_romfs_up_to_date(&fs, dir, bin));
+        // removing a file updates the directory
+        let fs = fs.file(bin, "", 4).dir("romfs/data", 5);
+        assert!(!is_romfs_up_to_date(&fs, dir, bin));
+    }
+}
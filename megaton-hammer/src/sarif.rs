@@ -0,0 +1,110 @@
+//! SARIF report for checker findings and compiler diagnostics, for code scanning integration in CI.
+//!
+//! See <https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html>
+
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+use crate::fs;
+
+const RULE_UNRESOLVED_SYMBOL: &str = "unresolved-symbol";
+const RULE_COMPILER_DIAGNOSTIC: &str = "compiler-diagnostic";
+
+/// Write a SARIF 2.1.0 report of the unresolved symbols found in `binary`, and the
+/// compiler `diagnostics`
+///
+/// The report is written even if there are no findings, so CI can always upload it.
+pub fn write_sarif<SReport, SBinary>(
+    report: SReport,
+    binary: SBinary,
+    missing_symbols: &[String],
+    diagnostics: &[Diagnostic],
+) -> Result<(), Error>
+where
+    SReport: AsRef<Path>,
+    SBinary: AsRef<Path>,
+{
+    let binary = binary.as_ref().display().to_string();
+    let results = missing_symbols
+        .iter()
+        .map(|symbol| {
+            json!({
+                "ruleId": RULE_UNRESOLVED_SYMBOL,
+                "level": "error",
+                "message": {
+                    "text": format!("Unresolved dynamic symbol `{symbol}`"),
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": binary, "index": 0 },
+                    },
+                }],
+            })
+        })
+        .chain(diagnostics.iter().map(|diagnostic| {
+            let level = if diagnostic.is_error() {
+                "error"
+            } else if diagnostic.is_warning() {
+                "warning"
+            } else {
+                "note"
+            };
+            let locations = match &diagnostic.file {
+                Some(file) => json!([{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": file },
+                        "region": {
+                            "startLine": diagnostic.line,
+                            "startColumn": diagnostic.column,
+                        },
+                    },
+                }]),
+                None => json!([]),
+            };
+            json!({
+                "ruleId": RULE_COMPILER_DIAGNOSTIC,
+                "level": level,
+                "message": { "text": diagnostic.message },
+                "locations": locations,
+            })
+        }))
+        .collect::<Vec<_>>();
+
+    let sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "megaton",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/Pistonight/megaton",
+                    "rules": [{
+                        "id": RULE_UNRESOLVED_SYMBOL,
+                        "shortDescription": {
+                            "text": "Dynamic symbol cannot be resolved at runtime",
+                        },
+                        "help": {
+                            "text": "Include the symbol in the linker scripts, or add it to the `ignore` list in the `check` section.",
+                        },
+                    }, {
+                        "id": RULE_COMPILER_DIAGNOSTIC,
+                        "shortDescription": {
+                            "text": "Warning or error from the compiler",
+                        },
+                    }],
+                },
+            },
+            "artifacts": [{ "location": { "uri": binary } }],
+            "results": results,
+        }],
+    });
+
+    let report = report.as_ref();
+    let content = serde_json::to_string_pretty(&sarif)
+        .map_err(|e| Error::AccessFile(report.display().to_string(), std::io::Error::other(e)))?;
+    fs::write_file(report, content)
+}
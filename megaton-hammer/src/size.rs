@@ -0,0 +1,73 @@
+//! The `size` command for reporting section sizes
+//!
+//! The sizes are saved to `./target/megaton/<flavor>/<profile>/size.prev`,
+//! so the next run can print the difference.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::{elf, fs};
+
+/// The sections to report, in order
+const SECTIONS: [&str; 4] = [".text", ".rodata", ".data", ".bss"];
+
+/// Print the sizes of the sections in `elf_path`, with the delta against `prev_path` if it exists
+///
+/// The current sizes are then saved to `prev_path`.
+pub fn report_size(elf_path: &Path, prev_path: &Path) -> Result<(), Error> {
+    let sections = elf::read_section_headers(elf_path)?;
+    let mut sizes = BTreeMap::new();
+    for name in SECTIONS {
+        let size = sections
+            .iter()
+            .filter(|section| section.name == name)
+            .map(|section| section.size)
+            .sum::<u64>();
+        sizes.insert(name.to_string(), size);
+    }
+    let total = sizes.values().sum::<u64>();
+
+    let prev = std::fs::read_to_string(prev_path)
+        .ok()
+        .map(|content| parse_snapshot(&content));
+
+    println!("{:<10} {:>12} {:>12}", "section", "size", "delta");
+    let print_row = |name: &str, size: u64| {
+        let delta = match prev.as_ref().and_then(|prev| prev.get(name)) {
+            Some(prev_size) => format_delta(size as i64 - *prev_size as i64),
+            None => "-".to_string(),
+        };
+        println!("{:<10} {:>12} {:>12}", name, size, delta);
+    };
+    for name in SECTIONS {
+        print_row(name, sizes[name]);
+    }
+    print_row("total", total);
+
+    let mut snapshot = String::new();
+    for (name, size) in &sizes {
+        snapshot.push_str(&format!("{name} {size}\n"));
+    }
+    snapshot.push_str(&format!("total {total}\n"));
+    fs::write_file(prev_path, snapshot)
+}
+
+/// Parse a `size.prev` snapshot, which has a `<section> <size>` pair on each line
+fn parse_snapshot(content: &str) -> BTreeMap<String, u64> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (name, size) = line.split_once(' ')?;
+            Some((name.to_string(), size.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+fn format_delta(delta: i64) -> String {
+    if delta > 0 {
+        format!("+{}", delta)
+    } else {
+        delta.to_string()
+    }
+}
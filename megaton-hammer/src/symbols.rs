@@ -0,0 +1,83 @@
+//! The `symbols` command for inspecting dynamic symbol sets
+
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::error::Error;
+use crate::infoln;
+use crate::objdump::{dump_symbols, parse_syms_file};
+
+/// Load the dynamic symbols from either an ELF (using `objdump -T`) or a `.syms` file
+pub fn load_symbols<SObjDump, SPath>(
+    objdump: SObjDump,
+    path: SPath,
+) -> Result<BTreeSet<String>, Error>
+where
+    SObjDump: AsRef<Path>,
+    SPath: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if is_elf(path)? {
+        return dump_symbols(objdump, path);
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    let mut symbols = BTreeSet::new();
+    parse_syms_file(&path.display().to_string(), &content, &mut symbols)?;
+    Ok(symbols)
+}
+
+/// Print the symbols that are in `b` but not in `a` (added), and in `a` but not in `b` (removed)
+pub fn diff_symbols<SObjDump, SA, SB>(
+    objdump: SObjDump,
+    a: SA,
+    b: SB,
+    json: bool,
+) -> Result<(), Error>
+where
+    SObjDump: AsRef<Path>,
+    SA: AsRef<Path>,
+    SB: AsRef<Path>,
+{
+    let a = load_symbols(objdump.as_ref(), a)?;
+    let b = load_symbols(objdump.as_ref(), b)?;
+    let added = b.difference(&a).collect::<Vec<_>>();
+    let removed = a.difference(&b).collect::<Vec<_>>();
+
+    if json {
+        let output = json!({
+            "added": added,
+            "removed": removed,
+        });
+        println!("{}", output);
+        return Ok(());
+    }
+
+    for symbol in &added {
+        println!("+ {}", symbol);
+    }
+    for symbol in &removed {
+        println!("- {}", symbol);
+    }
+    infoln!(
+        "Compared",
+        "{} added, {} removed",
+        added.len(),
+        removed.len()
+    );
+    Ok(())
+}
+
+/// Check the magic bytes to see if the file is an ELF
+fn is_elf(path: &Path) -> Result<bool, Error> {
+    let mut file =
+        std::fs::File::open(path).map_err(|e| Error::AccessFile(path.display().to_string(), e))?;
+    let mut magic = [0u8; 4];
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == b"\x7fELF"),
+        Err(_) => Ok(false),
+    }
+}
@@ -1,3 +1 @@
-fn main() {
-    println!("Hello, world!");
-}
+//! Placeholder for the megaton runtime library
@@ -59,7 +59,10 @@ extern char __module_start;
 /// Module entrypoint called by rtld
 void megaton_entrypoint(void);
 
-/// Default abort handler
+/// Flush the stdio bootstrapped on the Rust side (no-op if there is no stdio)
+extern void megaton_stdio_flush();
+
+/// Default abort handler. Flushes the stdio before aborting
 __attribute__((noreturn)) void megaton_default_abort(int code);
 }
 
@@ -1,5 +1,6 @@
 use proc_macro::TokenStream;
-use syn::{ItemFn, Meta, LitStr};
+use syn::punctuated::Punctuated;
+use syn::{Expr, ItemFn, Meta, Token};
 
 type TokenStream2 = proc_macro2::TokenStream;
 /// Implementation of the `#[megaton::bootstrap]` attribute.
@@ -43,6 +44,13 @@ pub fn bootstrap_impl(item: TokenStream) -> TokenStream {
     };
 
     expanded.extend(megaton_rust_main);
+    // there is no stdio binding yet, so output from the print macros is discarded
+    expanded.extend(quote::quote! {
+        #[no_mangle]
+        pub extern "C" fn megaton_stdio_write(_data: *const u8, _len: usize) {}
+        #[no_mangle]
+        pub extern "C" fn megaton_stdio_flush() {}
+    });
     for attr in keep_attrs {
         expanded.extend(quote::quote! { #attr });
     }
@@ -58,31 +66,76 @@ pub fn bootstrap_impl(item: TokenStream) -> TokenStream {
     expanded.into()
 }
 
+/// Generate the module name from the `#[module(...)]` attribute
+///
+/// The attribute can be any constant `&str` expression, like a string literal,
+/// a path to a `const`, or `env!("CARGO_PKG_NAME")`. The length of the name
+/// is computed at compile time from the constant, so an expression that isn't
+/// const is a compile error at the generated `MEGATON_MODULE_NAME`.
+///
+/// With `capacity = N` after the name, a mutable copy of the name with room for
+/// `N` bytes is generated as well. `megaton_module_name()` returns the copy, and
+/// `megaton::set_module_name` updates it. `module_name()` returns the current name
+/// by value instead of as a `&'static str`.
 pub fn declare_module_name(attr: TokenStream) -> TokenStream {
-    let literal = syn::parse_macro_input!(attr as LitStr);
-    let value = literal.value();
-    let len = value.len();
-    let mut byte_array = TokenStream2::new();
-    for byte in value.bytes() {
-        byte_array.extend(quote::quote! { #byte, });
+    let args = syn::parse_macro_input!(attr with Punctuated::<Expr, Token![,]>::parse_terminated);
+    let mut args = args.into_iter();
+    let Some(expr) = args.next() else {
+        panic!("Missing module name!. Please add #[module(\"...\")].");
+    };
+    let capacity = args.next().map(|arg| match arg {
+        Expr::Assign(assign) if matches!(&*assign.left, Expr::Path(p) if p.path.is_ident("capacity")) => {
+            *assign.right
+        }
+        _ => panic!("Expected `capacity = N` after the module name."),
+    });
+    if args.next().is_some() {
+        panic!("Too many arguments in #[module(...)]. Expected the name and an optional `capacity = N`.");
     }
 
-    
-    let out = quote::quote! {
+    let declare_const = quote::quote! {
+        const MEGATON_MODULE_NAME: &str = #expr;
         #[link_section = ".nx-module-name"]
         #[used]
-        static NX_MODULE_NAME: megaton::ModuleName<[u8; #len]> = 
-            megaton::ModuleName::new(#len as u32, [#byte_array]);
-        #[no_mangle]
-        pub extern "C" fn megaton_module_name() -> *const megaton::ModuleName<[u8; #len]> {
-            &NX_MODULE_NAME as *const _
-        }
-        pub const fn module_name() -> &'static str {
-            #literal
-        }
+        static NX_MODULE_NAME: megaton::ModuleName<[u8; MEGATON_MODULE_NAME.len()]> =
+            megaton::ModuleName::from_str(MEGATON_MODULE_NAME);
+    };
+
+    let out = match capacity {
+        None => quote::quote! {
+            #declare_const
+            #[no_mangle]
+            pub extern "C" fn megaton_module_name() -> *const megaton::ModuleName<[u8; MEGATON_MODULE_NAME.len()]> {
+                &NX_MODULE_NAME as *const _
+            }
+            #[no_mangle]
+            pub fn megaton_set_module_name(_name: &str) -> Result<(), megaton::SetModuleNameError> {
+                Err(megaton::SetModuleNameError::Const)
+            }
+            pub const fn module_name() -> &'static str {
+                MEGATON_MODULE_NAME
+            }
+        },
+        Some(capacity) => quote::quote! {
+            #declare_const
+            const MEGATON_MODULE_NAME_CAPACITY: usize = #capacity;
+            // `.nx-module-name` is read-only, so the mutable copy is a regular static
+            static mut MEGATON_RUNTIME_MODULE_NAME: megaton::ModuleName<[u8; MEGATON_MODULE_NAME_CAPACITY]> =
+                megaton::ModuleName::with_capacity(MEGATON_MODULE_NAME);
+            #[no_mangle]
+            pub extern "C" fn megaton_module_name() -> *const megaton::ModuleName<[u8; MEGATON_MODULE_NAME_CAPACITY]> {
+                unsafe { core::ptr::addr_of!(MEGATON_RUNTIME_MODULE_NAME) }
+            }
+            #[no_mangle]
+            pub fn megaton_set_module_name(name: &str) -> Result<(), megaton::SetModuleNameError> {
+                unsafe { (*core::ptr::addr_of_mut!(MEGATON_RUNTIME_MODULE_NAME)).set(name) }
+            }
+            // returned by value, since a reference would observe later calls to `set_module_name`
+            pub fn module_name() -> megaton::ModuleName<[u8; MEGATON_MODULE_NAME_CAPACITY]> {
+                unsafe { core::ptr::addr_of!(MEGATON_RUNTIME_MODULE_NAME).read() }
+            }
+        },
     };
 
     out.into()
 }
-
-
@@ -32,6 +32,19 @@ use proc_macro::TokenStream;
 /// - `megaton_module_name()` function to let C code access the module name struct.
 /// - `module_name()` function lets Rust code access the module name as a `&'static str`.
 ///
+/// Besides a string literal, the name can be any constant `&str` expression, such as
+/// `#[module(env!("CARGO_PKG_NAME"))]` or the path to a `const`. It must be evaluable at
+/// compile time, since the length of the name is part of the type of the module name struct.
+///
+/// If the name is only known at runtime, add a capacity, like
+/// `#[module("my-module", capacity = 64)]`. The module name struct then has room for up to
+/// `capacity` bytes, and `megaton::set_module_name` replaces the name (bounds-checked).
+/// `megaton_module_name()` returns the current name. `module_name()` returns a copy of the
+/// current name as a `megaton::ModuleName`, instead of a `&'static str`, so a name read
+/// earlier doesn't change when the name is set. Set it early in `main`, before the name is
+/// read. The loader reads the name from the binary before `main`, so it always sees the
+/// name given in the attribute.
+///
 /// ## `abort`
 /// Required. Specify abort handling behavior
 #[proc_macro_attribute]
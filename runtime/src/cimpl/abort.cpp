@@ -1,6 +1,8 @@
 #include <megaton.hpp>
 
 extern "C" __attribute__((noreturn)) void megaton_default_abort(int code) {
+    // make sure pending output (like the panic message) is written out
+    megaton_stdio_flush();
     // Credit: exlaunch/source/lib/diag/abort.cpp
     register s64 addr __asm__("x27") = 0x6969696969696969;
     register s64 val __asm__("x28")  = code;
@@ -1,7 +1,11 @@
 #![no_std]
-///
+use core::fmt;
+
+pub mod stdio;
+
+/// The module name, placed in the `.nx-module-name` section
+#[derive(Clone, Copy)]
 #[repr(C, packed(1))]
-#[derive(Debug)]
 pub struct ModuleName<S> {
     unknown: u32,
     /// A 4-byte integer indicating the length of the module name (does not include null byte)
@@ -19,8 +23,168 @@ impl<S> ModuleName<S> {
             null: 0,
         }
     }
+
+    /// Get the length of the module name, not including the null byte
+    pub const fn len(&self) -> u32 {
+        // the packed field is copied out, never referenced
+        self.len
+    }
+
+    /// Check if the module name is empty
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<const N: usize> ModuleName<[u8; N]> {
+    /// Create the module name from a string, which must be exactly `N` bytes long
+    ///
+    /// When used to initialize a `static` or `const`, a length mismatch is a compile error.
+    ///
+    /// ```rust
+    /// static NAME: megaton::ModuleName<[u8; 9]> = megaton::ModuleName::from_str("my-module");
+    /// ```
+    pub const fn from_str(name: &'static str) -> Self {
+        let bytes = name.as_bytes();
+        assert!(bytes.len() == N, "module name length does not match N");
+        let mut array = [0u8; N];
+        let mut i = 0;
+        while i < N {
+            array[i] = bytes[i];
+            i += 1;
+        }
+        Self::new(N as u32, array)
+    }
+
+    /// Create a module name that can hold up to `N` bytes, initialized with `name`
+    ///
+    /// The unused bytes are zeroed. A name longer than `N` is a compile error
+    /// when used to initialize a `static` or `const`.
+    pub const fn with_capacity(name: &'static str) -> Self {
+        let bytes = name.as_bytes();
+        assert!(bytes.len() <= N, "module name is longer than the capacity");
+        let mut array = [0u8; N];
+        let mut i = 0;
+        while i < bytes.len() {
+            array[i] = bytes[i];
+            i += 1;
+        }
+        Self::new(bytes.len() as u32, array)
+    }
+
+    /// Get the maximum length of the module name
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Replace the module name, updating the buffer and `len`
+    ///
+    /// The bytes after the new name are zeroed, so the name stays null-terminated.
+    /// Returns an error and leaves the name unchanged if `name` is longer than `N`.
+    pub fn set(&mut self, name: &str) -> Result<(), SetModuleNameError> {
+        let bytes = name.as_bytes();
+        if bytes.len() > N {
+            return Err(SetModuleNameError::TooLong {
+                len: bytes.len(),
+                capacity: N,
+            });
+        }
+        let mut array = [0u8; N];
+        array[..bytes.len()].copy_from_slice(bytes);
+        // the packed fields are assigned by value, never referenced
+        self.name = array;
+        self.len = bytes.len() as u32;
+        Ok(())
+    }
+
+    /// Get the bytes of the module name, not including the null byte
+    pub const fn as_bytes(&self) -> &[u8] {
+        // `name` can't be referenced directly since it's a packed field,
+        // but bytes have an alignment of 1, so a slice from its address is always aligned
+        let name = core::ptr::addr_of!(self.name) as *const u8;
+        let len = if (self.len() as usize) < N {
+            self.len() as usize
+        } else {
+            N
+        };
+        unsafe { core::slice::from_raw_parts(name, len) }
+    }
+
+    /// Get the module name as a string, or `None` if it's not valid UTF-8
+    pub const fn as_str(&self) -> Option<&str> {
+        match core::str::from_utf8(self.as_bytes()) {
+            Ok(name) => Some(name),
+            Err(_) => None,
+        }
+    }
+}
+
+impl<const N: usize> fmt::Display for ModuleName<[u8; N]> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_str() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:?}", self.as_bytes()),
+        }
+    }
+}
+
+impl<const N: usize> fmt::Debug for ModuleName<[u8; N]> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModuleName")
+            .field("len", &self.len())
+            .field("name", &format_args!("{:?}", self.as_str().unwrap_or_default()))
+            .finish()
+    }
 }
 static_assertions::assert_eq_size!(ModuleName<[u8; 10]>, [u8; 19]);
+static_assertions::assert_eq_align!(ModuleName<[u8; 10]>, u8);
+
+/// Error from [`set_module_name`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetModuleNameError {
+    /// The name is longer than the capacity of the module name buffer
+    TooLong { len: usize, capacity: usize },
+    /// The module name is a constant. Use `#[module(..., capacity = N)]` to allow setting it
+    Const,
+}
+
+impl fmt::Display for SetModuleNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong { len, capacity } => write!(
+                f,
+                "module name of {} bytes is longer than the capacity of {} bytes",
+                len, capacity
+            ),
+            Self::Const => f.write_str("module name is a constant"),
+        }
+    }
+}
+
+extern "Rust" {
+    /// Generated by `#[megaton::bootstrap]` from the `#[module(...)]` attribute
+    fn megaton_set_module_name(name: &str) -> Result<(), SetModuleNameError>;
+}
+
+/// Set the module name returned by `megaton_module_name()` at runtime
+///
+/// This requires the module name to be declared with a capacity, like
+/// `#[module("my-module", capacity = 64)]`. Otherwise, it returns [`SetModuleNameError::Const`].
+/// A name longer than the capacity is rejected and the current name is kept.
+///
+/// Call it early in `main`, before the name is read. The name set here is what
+/// `megaton_module_name()` and `module_name()` return afterwards. `module_name()` returns
+/// a copy, so a name read before this call keeps the old value. The loader reads
+/// the name embedded in the `.nx-module-name` section when the module is loaded,
+/// which is before `main`, so the loader always sees the initial name.
+///
+/// # Safety
+/// The module name is not synchronized. No other thread may read the module name
+/// while it's being set. A reference created from the pointer returned by
+/// `megaton_module_name()` must not be alive during this call.
+pub unsafe fn set_module_name(name: &str) -> Result<(), SetModuleNameError> {
+    megaton_set_module_name(name)
+}
 
 /// Rust side initialization, called before rust's main
 pub fn bootstrap_rust() {
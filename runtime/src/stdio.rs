@@ -0,0 +1,107 @@
+//! Output through the stdio bound by `#[megaton::bootstrap]`
+//!
+//! Use the [`print!`](crate::print), [`println!`](crate::println),
+//! [`eprint!`](crate::eprint) and [`eprintln!`](crate::eprintln) macros instead of
+//! the functions here directly.
+//!
+//! The output is formatted into a buffer of [`BUFFER_SIZE`] bytes on the stack, so no
+//! allocation is needed. When the buffer is full, it is written out and reused, so long
+//! messages are never truncated, but one message may reach the stdio in multiple writes.
+//! A UTF-8 character may be split across two writes.
+//!
+//! There is no separate stderr. The `eprint` macros write to the same stdio.
+
+use core::fmt::{self, Write};
+
+/// Size of the stack buffer used for formatting
+pub const BUFFER_SIZE: usize = 256;
+
+extern "C" {
+    /// Generated by `#[megaton::bootstrap]`. Does nothing when there is no stdio.
+    fn megaton_stdio_write(data: *const u8, len: usize);
+    /// Generated by `#[megaton::bootstrap]`. Does nothing when there is no stdio.
+    fn megaton_stdio_flush();
+}
+
+/// Flush the output buffered by the stdio, if any
+///
+/// The default abort handler calls this before aborting, so pending output is not lost.
+pub fn flush() {
+    unsafe { megaton_stdio_flush() };
+}
+
+struct StdioWriter {
+    buffer: [u8; BUFFER_SIZE],
+    len: usize,
+}
+
+impl StdioWriter {
+    fn flush(&mut self) {
+        if self.len > 0 {
+            unsafe { megaton_stdio_write(self.buffer.as_ptr(), self.len) };
+            self.len = 0;
+        }
+    }
+}
+
+impl Write for StdioWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            if self.len == BUFFER_SIZE {
+                self.flush();
+            }
+            let n = bytes.len().min(BUFFER_SIZE - self.len);
+            self.buffer[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+        Ok(())
+    }
+}
+
+/// Format `args` and write them to the stdio. Used by the print macros.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let mut writer = StdioWriter {
+        buffer: [0; BUFFER_SIZE],
+        len: 0,
+    };
+    let _ = writer.write_fmt(args);
+    writer.flush();
+}
+
+/// Print to the stdio bound by `#[megaton::bootstrap]`
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::stdio::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print to the stdio bound by `#[megaton::bootstrap]`, with a newline
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::stdio::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Same as [`print!`], since there is no separate stderr
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::print!($($arg)*)
+    };
+}
+
+/// Same as [`println!`], since there is no separate stderr
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => {
+        $crate::println!($($arg)*)
+    };
+}